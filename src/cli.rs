@@ -31,7 +31,6 @@ Run multiple commands on the same ephemeral host:
     docker --context greeter run debian:bookworm echo hello again
 "#},
 )]
-
 pub struct Cli {
     #[command(flatten)]
     what_to_run: WhatToRun,
@@ -47,6 +46,8 @@ pub struct Cli {
 #[command(next_help_heading = "Task (mutually exclusive)", max_term_width = 80)]
 pub struct WhatToRun {
     /// The subprocess to run.
+    ///
+    /// fleeting exits with its exit code, or with 75 if the provider interrupted the instance (e.g. to reclaim spot capacity) while it ran.
     #[arg(trailing_var_arg = true, global = true)]
     pub command: Option<Vec<String>>,
 
@@ -147,7 +148,7 @@ impl Cli {
                 self.logging.init(Some(format!(
                     "fleeting[{}{}{}]: ",
                     std::process::id(),
-                    if let Some(_) = &self.worker.custom_context_name { "/" } else { "" },
+                    if self.worker.custom_context_name.is_some() { "/" } else { "" },
                     if let Some(s) = &self.worker.custom_context_name { s.as_str() } else { "" },
                 )))?;
 
@@ -227,7 +228,7 @@ async fn waitpid(pid: u32) -> anyhow::Result<()> {
         // Must recreate `system` to remove dead processes, see `refresh_processes_specifics` docs.
         let mut system = sysinfo::System::new();
         system.refresh_processes_specifics(sysinfo::ProcessesToUpdate::Some(&[pid]), sysinfo::ProcessRefreshKind::new());
-        if let Some(_) = system.process(pid) {
+        if system.process(pid).is_some() {
             sleep(Duration::from_secs(1)).await;
         } else {
             break Ok(());
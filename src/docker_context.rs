@@ -1,9 +1,12 @@
 use anyhow::Context;
 use core::str;
-use futures::{future::RemoteHandle, FutureExt as _};
+use futures::{
+    future::{BoxFuture, RemoteHandle},
+    FutureExt as _,
+};
 use rcgen::{Certificate, CertifiedKey};
 use serde_json::json;
-use std::{fs, future::Future, net::Ipv4Addr, path::PathBuf, task::Poll};
+use std::{fs, future::Future, net::SocketAddrV4, path::PathBuf, task::Poll};
 
 pub struct DockerContext {
     name: String,
@@ -11,16 +14,19 @@ pub struct DockerContext {
     tls_dir: PathBuf,
     keepalive_handle: RemoteHandle<anyhow::Result<()>>,
     dockerd_handle: RemoteHandle<anyhow::Result<()>>,
+    interruption_handle: Option<RemoteHandle<anyhow::Result<()>>>,
+    destroy: Option<BoxFuture<'static, anyhow::Result<()>>>,
 }
 
 impl DockerContext {
     pub fn new(
         name: impl Into<String>,
-        ip: Ipv4Addr,
+        docker_addr: SocketAddrV4,
         ca_cert: &Certificate,
         ckey: &CertifiedKey,
         keepalive_handle: RemoteHandle<anyhow::Result<()>>,
         dockerd_handle: RemoteHandle<anyhow::Result<()>>,
+        interruption_handle: Option<RemoteHandle<anyhow::Result<()>>>,
     ) -> anyhow::Result<Self> {
         let name = name.into();
         log::debug!("Creating docker context '{}'...", name);
@@ -29,7 +35,7 @@ impl DockerContext {
             "Metadata": {},
             "Endpoints": {
                 "docker": {
-                    "Host": format!("tcp://{ip}:2376"),
+                    "Host": format!("tcp://{docker_addr}"),
                     "SkipTLSVerify": false
                 }
             }
@@ -42,12 +48,18 @@ impl DockerContext {
             anyhow::bail!("Docker context '{name}' already exists")
         }
         fs::create_dir_all(&meta_dir)?;
-        fs::create_dir_all(&tls_dir.join("docker"))?;
+        fs::create_dir_all(tls_dir.join("docker"))?;
         fs::write(meta_dir.join("meta.json"), serde_json::to_string(&meta_json)?)?;
         fs::write(tls_dir.join("docker/ca.pem"), ca_cert.pem().as_bytes())?;
         fs::write(tls_dir.join("docker/cert.pem"), ckey.cert.pem().as_bytes())?;
         fs::write(tls_dir.join("docker/key.pem"), ckey.key_pair.serialize_pem().as_bytes())?;
-        Ok(Self { name, meta_dir, tls_dir, keepalive_handle, dockerd_handle })
+        Ok(Self { name, meta_dir, tls_dir, keepalive_handle, dockerd_handle, interruption_handle, destroy: None })
+    }
+
+    /// Sets the provider's hook for destroying the VM, which `wrap` runs once done.
+    pub fn with_destroy(mut self, destroy: Option<BoxFuture<'static, anyhow::Result<()>>>) -> Self {
+        self.destroy = destroy;
+        self
     }
 
     pub fn name(&self) -> &str {
@@ -57,21 +69,30 @@ impl DockerContext {
     /// Returns when either `task` completes or the context fails.
     /// If the tasks completes first, its return value is returned.
     /// If either the task or the context fail, `Err` is returned.
-    pub async fn wrap<F, FRet>(self, task: F) -> anyhow::Result<FRet>
+    /// Either way, the VM is destroyed afterwards if the provider requires it.
+    pub async fn wrap<F, FRet>(mut self, task: F) -> anyhow::Result<FRet>
     where
         F: Future<Output = anyhow::Result<FRet>>,
     {
-        tokio::select! {
-            result = self => {
+        let result = tokio::select! {
+            result = &mut self => {
                 match result {
                     Ok(()) => unreachable!("should not complete cleanly"),
-                    Err(e) => Err(anyhow::format_err!("docker context failed before task could be completed: {e:#}")),
+                    Err(e) => Err(e.context("docker context failed before task could be completed")),
                 }
             }
             result = task => {
                 result
             }
+        };
+
+        if let Some(destroy) = self.destroy.take() {
+            log::info!("Destroying instance...");
+            if let Err(e) = destroy.await {
+                log::error!("Failed to destroy instance: {e:#}");
+            }
         }
+        result
     }
 }
 
@@ -79,7 +100,10 @@ impl Future for DockerContext {
     type Output = anyhow::Result<()>;
 
     fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Self::Output> {
-        if let Poll::Ready(result) = self.keepalive_handle.poll_unpin(cx) {
+        // Checked first: keepalive and dockerd fail too once the instance is gone
+        if let Some(Poll::Ready(result)) = self.interruption_handle.as_mut().map(|handle| handle.poll_unpin(cx)) {
+            Poll::Ready(result)
+        } else if let Poll::Ready(result) = self.keepalive_handle.poll_unpin(cx) {
             Poll::Ready(result)
         } else if let Poll::Ready(result) = self.dockerd_handle.poll_unpin(cx) {
             Poll::Ready(result)
@@ -91,6 +115,10 @@ impl Future for DockerContext {
 
 impl Drop for DockerContext {
     fn drop(&mut self) {
+        // Dropped without `wrap` completing
+        if let Some(destroy) = self.destroy.take() {
+            crate::shutdown::defer_destroy(destroy);
+        }
         log::debug!("Deleting docker context '{}'...", self.name);
         if let Err(e) = fs::remove_dir_all(&self.meta_dir).context("deleting docker context meta dir") {
             log::error!("{e:#}");
@@ -101,6 +129,28 @@ impl Drop for DockerContext {
     }
 }
 
+/// The provider announced it is about to interrupt the instance, e.g. to reclaim spot capacity.
+///
+/// Lets callers tell preemptions, which are worth retrying, apart from other failures.
+#[derive(Debug)]
+pub struct InstanceInterrupted {
+    /// As reported by the provider.
+    pub notice: String,
+}
+
+impl InstanceInterrupted {
+    /// fleeting's exit code when the instance was interrupted, EX_TEMPFAIL from sysexits.h.
+    pub const EXIT_CODE: u8 = 75;
+}
+
+impl std::fmt::Display for InstanceInterrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "instance interrupted by provider: {}", self.notice)
+    }
+}
+
+impl std::error::Error for InstanceInterrupted {}
+
 fn sha256(x: &[u8]) -> String {
     use sha2::{Digest, Sha256};
     let mut hasher = Sha256::new();
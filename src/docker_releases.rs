@@ -11,7 +11,6 @@ pub async fn get_docker_releases(arch: Arch) -> anyhow::Result<BTreeMap<Version,
     let html = Html::parse_document(&html);
     let releases = html
         .select(&Selector::parse("a[href]").unwrap())
-        .into_iter()
         .map(|a| a.attr("href").unwrap())
         .flat_map(|href| Regex::new(r"^docker-(\d+\.\d+\.\d+)\.tgz$").unwrap().captures(href))
         .flat_map(|captures| {
@@ -8,5 +8,7 @@ pub mod logging;
 pub mod shutdown;
 pub mod ssh;
 pub mod steps;
+pub mod ubuntu_release;
+pub mod unix_http;
 pub mod vm_providers;
 pub mod worker;
@@ -113,8 +113,13 @@ impl Log for Logger {
             Level::Trace => "trace: ",
         };
 
+        let scope_prefix = match steps::scope_label() {
+            Some(label) => format!("{label}: "),
+            None => String::new(),
+        };
+
         // Write in a single write
-        let stderr_line = format!("{step_prefix}{level_prefix}{}\n", record.args());
+        let stderr_line = format!("{step_prefix}{scope_prefix}{level_prefix}{}\n", record.args());
         std::io::stderr().write_all(stderr_line.as_bytes()).unwrap_or(());
 
         if let Some((file, file_prefix)) = &self.file_logging {
@@ -1,11 +1,12 @@
 use clap::Parser;
-use fleeting::cli::Cli;
+use fleeting::{cli::Cli, docker_context::InstanceInterrupted};
 use std::process::ExitCode;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> ExitCode {
     let cli = Cli::parse();
-    tokio::select! {
+    // Dropping `cli.run()` leaves VMs it has not destroyed yet to `destroy_pending`
+    let exit_code = tokio::select! {
         biased;
         () = fleeting::shutdown::wait_for_signal() => {
             ExitCode::FAILURE
@@ -13,8 +14,14 @@ async fn main() -> ExitCode {
         result = cli.run() => {
             result.unwrap_or_else(|internal_error: anyhow::Error| {
                 log::error!("{internal_error:#}");
-                ExitCode::FAILURE
+                if internal_error.chain().any(|e| e.is::<InstanceInterrupted>()) {
+                    ExitCode::from(InstanceInterrupted::EXIT_CODE)
+                } else {
+                    ExitCode::FAILURE
+                }
             })
         }
-    }
+    };
+    fleeting::shutdown::destroy_pending().await;
+    exit_code
 }
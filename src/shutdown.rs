@@ -1,3 +1,28 @@
+use futures::future::BoxFuture;
+use std::sync::Mutex;
+
+/// Destroy hooks of VMs whose owner was dropped before it could run them, e.g. when a signal interrupted the run.
+static PENDING_DESTROYS: Mutex<Vec<BoxFuture<'static, anyhow::Result<()>>>> = Mutex::new(Vec::new());
+
+/// Keeps a VM's destroy hook for `destroy_pending` to run before the process exits.
+pub fn defer_destroy(destroy: BoxFuture<'static, anyhow::Result<()>>) {
+    PENDING_DESTROYS.lock().expect("not poisoned").push(destroy);
+}
+
+/// Runs the destroy hooks passed to `defer_destroy`.
+pub async fn destroy_pending() {
+    let destroys = std::mem::take(&mut *PENDING_DESTROYS.lock().expect("not poisoned"));
+    if destroys.is_empty() {
+        return;
+    }
+    log::info!("Destroying instances...");
+    for result in futures::future::join_all(destroys).await {
+        if let Err(e) = result {
+            log::error!("Failed to destroy instance: {e:#}");
+        }
+    }
+}
+
 /// Registers signal handlers and waits for a signal that
 /// indicates a shutdown request.
 pub async fn wait_for_signal() {
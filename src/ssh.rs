@@ -79,7 +79,7 @@ impl ChannelExt for Channel<Msg> {
                 russh::ChannelMsg::Data { data } => {
                     handle_data_msg(&mut stdout_buf, &stdout_mode, data)?;
                 }
-                russh::ChannelMsg::ExtendedData { data, ext } if ext == 1 => {
+                russh::ChannelMsg::ExtendedData { data, ext: 1 } => {
                     handle_data_msg(&mut stderr_buf, &stderr_mode, data)?;
                 }
                 russh::ChannelMsg::ExitStatus { exit_status } => {
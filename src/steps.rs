@@ -1,13 +1,40 @@
 use number_generics::{Number, One};
 use std::{
+    future::Future,
     marker::PhantomData,
     sync::{Arc, LazyLock, Mutex},
 };
 
-static CURRENT_STEP: LazyLock<Mutex<Option<Arc<Step>>>> = LazyLock::new(|| Mutex::new(None));
+static CURRENT_STEP: LazyLock<Arc<Mutex<Option<Arc<Step>>>>> = LazyLock::new(|| Arc::new(Mutex::new(None)));
+
+tokio::task_local! {
+    static SCOPE: Scope;
+}
+
+/// Steps of concurrently running futures, see `scope`.
+struct Scope {
+    label: String,
+    current_step: Arc<Mutex<Option<Arc<Step>>>>,
+}
+
+/// Runs `future` with its own step stack, starting at the current step, so it can run steps concurrently with others.
+/// Log lines from within are labeled with `label`.
+pub async fn scope<F: Future>(label: impl Into<String>, future: F) -> F::Output {
+    let scope = Scope { label: label.into(), current_step: Arc::new(Mutex::new(current())) };
+    SCOPE.scope(scope, future).await
+}
+
+/// Label of the enclosing `scope`, if any.
+pub fn scope_label() -> Option<String> {
+    SCOPE.try_with(|scope| scope.label.clone()).ok()
+}
+
+fn current_step_slot() -> Arc<Mutex<Option<Arc<Step>>>> {
+    SCOPE.try_with(|scope| scope.current_step.clone()).unwrap_or_else(|_| CURRENT_STEP.clone())
+}
 
 pub fn current() -> Option<Arc<Step>> {
-    CURRENT_STEP.lock().unwrap().clone()
+    current_step_slot().lock().unwrap().clone()
 }
 
 pub struct Step {
@@ -23,7 +50,8 @@ pub struct StepHandle<Preceding, Remaining> {
 
 impl<Preceding: Number, Remaining: Number> StepHandle<Preceding, Remaining> {
     fn new() -> Self {
-        let mut current_step_guard = CURRENT_STEP.lock().unwrap();
+        let slot = current_step_slot();
+        let mut current_step_guard = slot.lock().unwrap();
         let step = Arc::new(Step {
             parent: current_step_guard.take(),
             number: Preceding::len() + 1,
@@ -38,7 +66,8 @@ impl<Preceding: Number, Remaining: Number> StepHandle<Preceding, Remaining> {
 
 impl<Preceding, Remaining> Drop for StepHandle<Preceding, Remaining> {
     fn drop(&mut self) {
-        let mut current_step_guard = CURRENT_STEP.lock().unwrap();
+        let slot = current_step_slot();
+        let mut current_step_guard = slot.lock().unwrap();
         let current_step = current_step_guard.take().expect("a current step");
         assert!(
             Arc::ptr_eq(&current_step, &self.step),
@@ -135,7 +164,7 @@ pub mod fmt {
         let mut previous_step = &*previous_step_guard;
         let visible = loop {
             match previous_step {
-                Some(p) if Arc::ptr_eq(current_step, &p) => break false,
+                Some(p) if Arc::ptr_eq(current_step, p) => break false,
                 Some(p) => previous_step = &p.parent,
                 None => break true,
             }
@@ -0,0 +1,89 @@
+use std::{fmt, str::FromStr};
+
+/// Ubuntu release version, e.g. 24.04, which providers map to their Canonical image.
+///
+/// Providers booting an image the user prepares (container, kubernetes, openstack, proxmox, firecracker, ssh) take none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UbuntuRelease {
+    year: u8,
+    month: u8,
+}
+
+impl UbuntuRelease {
+    pub const fn new(year: u8, month: u8) -> Self {
+        Self { year, month }
+    }
+
+    /// Even-year April releases.
+    pub fn is_lts(&self) -> bool {
+        self.year.is_multiple_of(2) && self.month == 4
+    }
+
+    /// Version without the dot, e.g. 2404.
+    pub fn as_compact(&self) -> String {
+        format!("{:02}{:02}", self.year, self.month)
+    }
+
+    /// E.g. noble, for the releases some providers' image names still use.
+    pub fn codename(&self) -> Option<&'static str> {
+        Some(match (self.year, self.month) {
+            (20, 4) => "focal",
+            (20, 10) => "groovy",
+            (21, 4) => "hirsute",
+            (21, 10) => "impish",
+            (22, 4) => "jammy",
+            (22, 10) => "kinetic",
+            (23, 4) => "lunar",
+            (23, 10) => "mantic",
+            (24, 4) => "noble",
+            (24, 10) => "oracular",
+            (25, 4) => "plucky",
+            (25, 10) => "questing",
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for UbuntuRelease {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}.{:02}", self.year, self.month)
+    }
+}
+
+impl FromStr for UbuntuRelease {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parsed = s
+            .trim()
+            .split_once('.')
+            .and_then(|(year, month)| Some((year.parse().ok()?, month.parse().ok()?)));
+        match parsed {
+            Some((year, month @ (4 | 10))) => Ok(Self { year, month }),
+            _ => Err(anyhow::format_err!("Unknown Ubuntu release: {s} (expected e.g. 24.04)")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_releases() {
+        let release: UbuntuRelease = "24.04".parse().unwrap();
+        assert_eq!(release, UbuntuRelease::new(24, 4));
+        assert_eq!(release.to_string(), "24.04");
+        assert_eq!(release.as_compact(), "2404");
+        assert!(release.is_lts());
+
+        let release: UbuntuRelease = " 24.10 ".parse().unwrap();
+        assert_eq!(release, UbuntuRelease::new(24, 10));
+        assert!(!release.is_lts());
+        assert!(!UbuntuRelease::new(23, 4).is_lts());
+
+        for invalid in ["24", "24.05", "24.04.1", "noble", "", "24.4x"] {
+            assert!(invalid.parse::<UbuntuRelease>().is_err(), "{invalid} parsed");
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use anyhow::Context as _;
+use http_body_util::{BodyExt as _, Full};
+use hyper::{body::Bytes, Method, Request, StatusCode};
+use std::path::Path;
+
+/// Sends a single HTTP/1.1 request to a REST API listening on a Unix socket (e.g. Incus, Docker).
+pub async fn request(socket_path: &Path, method: Method, path: &str, body: Option<&serde_json::Value>) -> anyhow::Result<(StatusCode, Bytes)> {
+    log::debug!("{method} {path}");
+
+    #[cfg(unix)]
+    let stream = tokio::net::UnixStream::connect(socket_path)
+        .await
+        .with_context(|| format!("connecting to {socket_path:?}"))?;
+    #[cfg(not(unix))]
+    let stream: tokio::io::DuplexStream = anyhow::bail!("cannot connect to {socket_path:?}: Unix sockets are not supported on this platform");
+
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            log::debug!("Unix socket connection failed: {e:#}");
+        }
+    });
+
+    let request = Request::builder()
+        .method(method)
+        .uri(path)
+        .header(hyper::header::HOST, "localhost")
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Full::new(match body {
+            Some(body) => Bytes::from(serde_json::to_vec(body)?),
+            None => Bytes::new(),
+        }))?;
+    let response = sender.send_request(request).await?;
+    let status = response.status();
+    let body = response.into_body().collect().await?.to_bytes();
+    Ok((status, body))
+}
@@ -1,15 +1,21 @@
 #!/bin/bash
 set -eu -o pipefail
-trap "shutdown -h now" EXIT
 
-mkdir /fleeting
+# May already exist as the ssh provider's lock
+mkdir -p /fleeting
+
+# Clean up once keepalives stop (or setup fails)
+teardown() {
+{{teardown_command}}
+}
+trap teardown EXIT
 
 # Write otp for readback upon connection
 echo "{{otp}}" >/fleeting/otp
 
-# Allow connections
+# Allow connections, next to any existing keys. Marked, so reused hosts (see the ssh provider) can remove exactly these.
 mkdir -p /root/.ssh
-echo "{{authorized_keys}}" >/root/.ssh/authorized_keys
+echo "{{authorized_keys}}" | sed 's/$/ fleeting-run/' >>/root/.ssh/authorized_keys
 
 # Stay running while someone is extending the timeout
 touch /fleeting/keepalive
@@ -0,0 +1,659 @@
+use super::{instance_name, SpawnedVm, VmProvider};
+use crate::{command_ext::CommandExt as _, steps, ubuntu_release::UbuntuRelease};
+use anyhow::Context as _;
+use async_trait::async_trait;
+use base64::prelude::*;
+use clap::Args;
+use reqwest::{Method, StatusCode};
+use russh::keys::PublicKeyBase64 as _;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use std::{collections::HashMap, env, net::Ipv4Addr};
+use tokio::{
+    process::Command,
+    time::{sleep, Duration},
+};
+
+const MANAGEMENT_URL: &str = "https://management.azure.com";
+const COMPUTE_API_VERSION: &str = "2024-03-01";
+const NETWORK_API_VERSION: &str = "2023-11-01";
+const RESOURCES_API_VERSION: &str = "2022-09-01";
+const SKUS_API_VERSION: &str = "2021-07-01";
+const NETWORK_SECURITY_GROUP_NAME: &str = "fleeting";
+const VIRTUAL_NETWORK_NAME: &str = "fleeting";
+const SUBNET_NAME: &str = "default";
+
+/// Microsoft Azure Virtual Machines
+#[derive(Args, Clone)]
+#[command(
+    override_usage = color_print::cstr! {r#"<bold>fleeting</bold> <bold>azure</bold> [OPTIONS] [COMMAND]...
+
+<bold><underline>Authentication:</underline></bold>
+  - Environment variables (AZURE_TENANT_ID, AZURE_CLIENT_ID, AZURE_CLIENT_SECRET)
+  - Azure CLI (az login)
+
+<bold><underline>Limitations:</underline></bold>
+Azure VMs stop, but are not deleted, when shut down. fleeting collects garbage
+in the resource group at the beginning of the run, but you will be left with a
+small number of stopped VMs and will continue to pay for them and their disks.
+
+"#},)]
+pub struct Azure {
+    /// [default: $AZURE_SUBSCRIPTION_ID > Azure CLI's current subscription]
+    #[arg(long)]
+    subscription: Option<String>,
+
+    /// Resource group for the VMs and their network resources, created if needed [required]
+    #[arg(long)]
+    resource_group: String,
+
+    #[arg(long, default_value = "eastus")]
+    location: String,
+
+    #[arg(long, default_value = "Standard_B1s")]
+    vm_size: String,
+
+    /// Disk size, in GiBs.
+    #[arg(long)]
+    disk: Option<usize>,
+
+    /// Release of Canonical's Ubuntu image.
+    #[arg(long, default_value = "24.04")]
+    ubuntu_release: UbuntuRelease,
+}
+
+#[async_trait]
+impl VmProvider for Azure {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
+        let step = steps::start();
+        log::info!("Loading Azure configuration...");
+        let client = {
+            let (token, cli_subscription) = get_access_token().await?;
+            let subscription = match (&self.subscription, env::var("AZURE_SUBSCRIPTION_ID").ok(), cli_subscription) {
+                (Some(subscription), _, _) => subscription.clone(),
+                (None, Some(subscription), _) => subscription,
+                (None, None, Some(subscription)) => subscription,
+                (None, None, None) => anyhow::bail!("'--subscription' or AZURE_SUBSCRIPTION_ID required when not using Azure CLI"),
+            };
+            log::info!("Subscription: {subscription}");
+            Client {
+                http: reqwest::Client::new(),
+                token,
+                subscription_path: format!("/subscriptions/{subscription}"),
+                resource_group_path: format!("/subscriptions/{subscription}/resourceGroups/{}", self.resource_group),
+            }
+        };
+
+        let step = step.next();
+        log::info!("Delete stopped fleeting VMs and orphaned resources...");
+        {
+            let vms: Vec<Vm> = client
+                .list(
+                    &client.in_resource_group("Microsoft.Compute/virtualMachines?statusOnly=true"),
+                    COMPUTE_API_VERSION,
+                )
+                .await?;
+            let stopped_vms = vms
+                .into_iter()
+                .filter(|vm| vm.name.starts_with("fleeting-") && vm.power_state().is_some_and(|s| s == "stopped" || s == "deallocated"))
+                .collect::<Vec<_>>();
+            for vm in &stopped_vms {
+                // OS disk, NIC and public IP have `deleteOption: Delete`
+                client.delete(&vm.id, COMPUTE_API_VERSION).await?;
+            }
+            log::info!("{} VMs deleted", stopped_vms.len());
+
+            // Left behind if a VM failed to provision or was deleted manually.
+            #[derive(Deserialize)]
+            struct Resource {
+                id: String,
+                name: String,
+                properties: serde_json::Value,
+            }
+            let mut orphans = 0;
+            let unattached: [(&str, &str, fn(&serde_json::Value) -> bool); 3] = [
+                ("Microsoft.Network/networkInterfaces", NETWORK_API_VERSION, |p| {
+                    p.get("virtualMachine").is_none()
+                }),
+                ("Microsoft.Network/publicIPAddresses", NETWORK_API_VERSION, |p| {
+                    p.get("ipConfiguration").is_none()
+                }),
+                ("Microsoft.Compute/disks", COMPUTE_API_VERSION, |p| {
+                    p.get("diskState").is_some_and(|s| s == "Unattached")
+                }),
+            ];
+            for (resource_type, api_version, is_unattached) in unattached {
+                let resources: Vec<Resource> = client.list(&client.in_resource_group(resource_type), api_version).await?;
+                for resource in resources {
+                    if resource.name.starts_with("fleeting-") && is_unattached(&resource.properties) {
+                        client.delete(&resource.id, api_version).await?;
+                        orphans += 1;
+                    }
+                }
+            }
+            log::info!("{orphans} orphaned resources deleted");
+        }
+
+        let step = step.next();
+        log::info!("Looking up VM size...");
+        let (image_offer, image_sku) = {
+            #[derive(Deserialize)]
+            #[serde(rename_all = "camelCase")]
+            struct Sku {
+                resource_type: String,
+                name: String,
+                #[serde(default)]
+                capabilities: Vec<Capability>,
+            }
+            #[derive(Deserialize)]
+            struct Capability {
+                name: String,
+                value: String,
+            }
+            let skus: Vec<Sku> = client
+                .list(
+                    &format!(
+                        "{}/providers/Microsoft.Compute/skus?$filter=location eq '{}'",
+                        client.subscription_path, self.location
+                    ),
+                    SKUS_API_VERSION,
+                )
+                .await?;
+            let sku = skus
+                .into_iter()
+                .find(|sku| sku.resource_type == "virtualMachines" && sku.name.eq_ignore_ascii_case(&self.vm_size))
+                .ok_or(anyhow::format_err!("VM size {} not available in {}", self.vm_size, self.location))?;
+            let capabilities = sku.capabilities.into_iter().map(|c| (c.name, c.value)).collect::<HashMap<_, _>>();
+            log::debug!("{capabilities:?}");
+
+            let arm64 = capabilities.get("CpuArchitectureType").is_some_and(|a| a == "Arm64");
+            let gen2 = capabilities.get("HyperVGenerations").is_some_and(|g| g.contains("V2"));
+            image_reference(self.ubuntu_release, arm64, gen2)?
+        };
+
+        let step = step.next();
+        log::info!("Creating network resources if needed...");
+        let (network_security_group_id, subnet_id) = {
+            let resource_group: Option<serde_json::Value> = client.get_optional(&client.resource_group_path, RESOURCES_API_VERSION).await?;
+            if resource_group.is_none() {
+                client
+                    .put(&client.resource_group_path, RESOURCES_API_VERSION, json!({ "location": self.location }))
+                    .await?;
+                log::info!("{} (created)", self.resource_group);
+            }
+            client.wait_provisioned(&client.resource_group_path, RESOURCES_API_VERSION).await?;
+
+            let network_security_group_path = client.in_resource_group(&format!("Microsoft.Network/networkSecurityGroups/{NETWORK_SECURITY_GROUP_NAME}"));
+            let network_security_group: Option<serde_json::Value> = client.get_optional(&network_security_group_path, NETWORK_API_VERSION).await?;
+            if network_security_group.is_none() {
+                client
+                    .put(
+                        &network_security_group_path,
+                        NETWORK_API_VERSION,
+                        json!({
+                            "location": self.location,
+                            "properties": {
+                                "securityRules": [{
+                                    "name": "allow-inbound",
+                                    "properties": {
+                                        "priority": 100,
+                                        "direction": "Inbound",
+                                        "access": "Allow",
+                                        "protocol": "*",
+                                        "sourceAddressPrefix": "*",
+                                        "sourcePortRange": "*",
+                                        "destinationAddressPrefix": "*",
+                                        "destinationPortRange": "*",
+                                    },
+                                }],
+                            },
+                        }),
+                    )
+                    .await?;
+                log::info!("{NETWORK_SECURITY_GROUP_NAME} (created)");
+            }
+            // The VM cannot reference resources that are still provisioning, e.g. on the first run
+            client.wait_provisioned(&network_security_group_path, NETWORK_API_VERSION).await?;
+
+            let virtual_network_path = client.in_resource_group(&format!("Microsoft.Network/virtualNetworks/{VIRTUAL_NETWORK_NAME}"));
+            let virtual_network: Option<serde_json::Value> = client.get_optional(&virtual_network_path, NETWORK_API_VERSION).await?;
+            if virtual_network.is_none() {
+                client
+                    .put(
+                        &virtual_network_path,
+                        NETWORK_API_VERSION,
+                        json!({
+                            "location": self.location,
+                            "properties": {
+                                "addressSpace": { "addressPrefixes": ["10.0.0.0/16"] },
+                                "subnets": [{ "name": SUBNET_NAME, "properties": { "addressPrefix": "10.0.0.0/16" } }],
+                            },
+                        }),
+                    )
+                    .await?;
+                log::info!("{VIRTUAL_NETWORK_NAME} (created)");
+            }
+            client.wait_provisioned(&virtual_network_path, NETWORK_API_VERSION).await?;
+
+            (network_security_group_path, format!("{virtual_network_path}/subnets/{SUBNET_NAME}"))
+        };
+
+        let step = step.next();
+        log::info!("Launching a VM...");
+        let vm_path = {
+            let name = instance_name();
+            let vm_path = client.in_resource_group(&format!("Microsoft.Compute/virtualMachines/{name}"));
+
+            // Azure insists on an admin credential. Authorize a key nobody has, the real ones come via custom data.
+            let unused_key_pair = russh::keys::key::KeyPair::generate_ed25519().expect("key generated");
+            let unused_authorized_key = format!("{} {} fleeting-unused", unused_key_pair.name(), unused_key_pair.public_key_base64());
+
+            client
+                .put(
+                    &vm_path,
+                    COMPUTE_API_VERSION,
+                    json!({
+                        "location": self.location,
+                        "tags": { "fleeting": "true" },
+                        "properties": {
+                            "hardwareProfile": { "vmSize": self.vm_size },
+                            "storageProfile": {
+                                "imageReference": {
+                                    "publisher": "Canonical",
+                                    "offer": image_offer,
+                                    "sku": image_sku,
+                                    "version": "latest",
+                                },
+                                "osDisk": {
+                                    "name": name,
+                                    "createOption": "FromImage",
+                                    "deleteOption": "Delete",
+                                    "diskSizeGB": self.disk,
+                                    "managedDisk": { "storageAccountType": "StandardSSD_LRS" },
+                                },
+                            },
+                            "osProfile": {
+                                "computerName": name,
+                                "adminUsername": "fleeting",
+                                "customData": BASE64_STANDARD.encode(user_data),
+                                "linuxConfiguration": {
+                                    "disablePasswordAuthentication": true,
+                                    "ssh": {
+                                        "publicKeys": [{
+                                            "path": "/home/fleeting/.ssh/authorized_keys",
+                                            "keyData": unused_authorized_key,
+                                        }],
+                                    },
+                                },
+                            },
+                            "networkProfile": {
+                                "networkApiVersion": NETWORK_API_VERSION,
+                                "networkInterfaceConfigurations": [{
+                                    "name": name,
+                                    "properties": {
+                                        "primary": true,
+                                        "deleteOption": "Delete",
+                                        "networkSecurityGroup": { "id": network_security_group_id },
+                                        "ipConfigurations": [{
+                                            "name": name,
+                                            "properties": {
+                                                "subnet": { "id": subnet_id },
+                                                "publicIPAddressConfiguration": {
+                                                    "name": name,
+                                                    "sku": { "name": "Standard" },
+                                                    "properties": {
+                                                        "deleteOption": "Delete",
+                                                        "publicIPAllocationMethod": "Static",
+                                                    },
+                                                },
+                                            },
+                                        }],
+                                    },
+                                }],
+                            },
+                        },
+                    }),
+                )
+                .await?;
+            log::info!("{name}");
+            vm_path
+        };
+
+        let step = step.next();
+        log::info!("Waiting for VM to start...");
+        let public_ip = {
+            let vm = loop {
+                log::debug!("Retrieving VM status...");
+                let vm: Vm = client.get(&format!("{vm_path}?$expand=instanceView"), COMPUTE_API_VERSION).await?;
+                match vm.properties.provisioning_state.as_str() {
+                    "Creating" | "Updating" => sleep(Duration::from_secs(1)).await,
+                    "Succeeded" if vm.power_state() == Some("running") => break vm,
+                    "Succeeded" => sleep(Duration::from_secs(1)).await,
+                    state => anyhow::bail!("VM transitioned into provisioning state: {state}"),
+                }
+            };
+
+            #[derive(Deserialize)]
+            struct NetworkInterface {
+                properties: NetworkInterfaceProperties,
+            }
+            #[derive(Deserialize)]
+            #[serde(rename_all = "camelCase")]
+            struct NetworkInterfaceProperties {
+                ip_configurations: Vec<IpConfiguration>,
+            }
+            #[derive(Deserialize)]
+            struct IpConfiguration {
+                properties: IpConfigurationProperties,
+            }
+            #[derive(Deserialize)]
+            #[serde(rename_all = "camelCase")]
+            struct IpConfigurationProperties {
+                public_ip_address: Option<ResourceRef>,
+            }
+            #[derive(Deserialize)]
+            struct PublicIpAddress {
+                properties: PublicIpAddressProperties,
+            }
+            #[derive(Deserialize)]
+            #[serde(rename_all = "camelCase")]
+            struct PublicIpAddressProperties {
+                ip_address: Option<Ipv4Addr>,
+            }
+
+            let network_interface_ref = vm
+                .properties
+                .network_profile
+                .context("VM has no network profile")?
+                .network_interfaces
+                .one("network interface")?;
+            let network_interface: NetworkInterface = client.get(&network_interface_ref.id, NETWORK_API_VERSION).await?;
+            let public_ip_address_ref = network_interface
+                .properties
+                .ip_configurations
+                .one("IP configuration")?
+                .properties
+                .public_ip_address
+                .context("network interface has no public IP address")?;
+            let public_ip_address: PublicIpAddress = client.get(&public_ip_address_ref.id, NETWORK_API_VERSION).await?;
+            public_ip_address.properties.ip_address.context("public IP address not allocated")?
+        };
+
+        steps::end(step);
+        Ok(public_ip.into())
+    }
+}
+
+/// Offer and SKU of Canonical's image, which changed naming schemes with 24.04:
+/// e.g. ubuntu-24_04-lts with server-arm64, but 0001-com-ubuntu-server-jammy with 22_04-lts-arm64.
+fn image_reference(release: UbuntuRelease, arm64: bool, gen2: bool) -> anyhow::Result<(String, String)> {
+    let version = release.to_string().replace('.', "_");
+    let lts = if release.is_lts() { "-lts" } else { "" };
+    if release >= UbuntuRelease::new(24, 4) {
+        let sku = match (arm64, gen2) {
+            (true, _) => "server-arm64",
+            (false, true) => "server",
+            (false, false) => "server-gen1",
+        };
+        Ok((format!("ubuntu-{version}{lts}"), sku.to_owned()))
+    } else {
+        let codename = release.codename().with_context(|| format!("no Azure image known for Ubuntu {release}"))?;
+        let suffix = match (arm64, gen2) {
+            (true, _) => "-arm64",
+            (false, true) => "-gen2",
+            (false, false) => "",
+        };
+        Ok((format!("0001-com-ubuntu-server-{codename}"), format!("{version}{lts}{suffix}")))
+    }
+}
+
+#[derive(Deserialize)]
+struct Vm {
+    id: String,
+    name: String,
+    properties: VmProperties,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VmProperties {
+    #[serde(default)]
+    provisioning_state: String,
+    instance_view: Option<InstanceView>,
+    network_profile: Option<NetworkProfile>,
+}
+
+#[derive(Deserialize)]
+struct InstanceView {
+    #[serde(default)]
+    statuses: Vec<InstanceViewStatus>,
+}
+
+#[derive(Deserialize)]
+struct InstanceViewStatus {
+    code: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NetworkProfile {
+    #[serde(default)]
+    network_interfaces: Vec<ResourceRef>,
+}
+
+#[derive(Deserialize)]
+struct ResourceRef {
+    id: String,
+}
+
+impl Vm {
+    /// E.g. "running", "stopped" or "deallocated".
+    fn power_state(&self) -> Option<&str> {
+        let instance_view = self.properties.instance_view.as_ref()?;
+        instance_view.statuses.iter().find_map(|status| status.code.strip_prefix("PowerState/"))
+    }
+}
+
+/// Returns the token and, if obtained from Azure CLI, the current subscription.
+async fn get_access_token() -> anyhow::Result<(String, Option<String>)> {
+    if let (Ok(tenant_id), Ok(client_id), Ok(client_secret)) = (env::var("AZURE_TENANT_ID"), env::var("AZURE_CLIENT_ID"), env::var("AZURE_CLIENT_SECRET")) {
+        log::debug!("Using client secret credentials");
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            access_token: String,
+        }
+        let response: TokenResponse = reqwest::Client::new()
+            .post(format!("https://login.microsoftonline.com/{tenant_id}/oauth2/v2.0/token"))
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", &client_id),
+                ("client_secret", &client_secret),
+                ("scope", &format!("{MANAGEMENT_URL}/.default")),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok((response.access_token, None))
+    } else {
+        log::debug!("Using Azure CLI credentials");
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AzAccessToken {
+            access_token: String,
+            subscription: String,
+        }
+        let token: AzAccessToken = Command::new("az")
+            .args(["account", "get-access-token", "--resource", MANAGEMENT_URL, "--output", "json"])
+            .capture_json()
+            .await?;
+        Ok((token.access_token, Some(token.subscription)))
+    }
+}
+
+struct Client {
+    http: reqwest::Client,
+    token: String,
+    subscription_path: String,
+    resource_group_path: String,
+}
+
+impl Client {
+    fn in_resource_group(&self, resource: &str) -> String {
+        format!("{}/providers/{resource}", self.resource_group_path)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, api_version: &str) -> anyhow::Result<T> {
+        let response = self.send(Method::GET, path, api_version, None).await?;
+        Ok(response.json().await?)
+    }
+
+    /// `None` if the resource (or its resource group) does not exist.
+    async fn get_optional<T: DeserializeOwned>(&self, path: &str, api_version: &str) -> anyhow::Result<Option<T>> {
+        match self.send(Method::GET, path, api_version, None).await {
+            Ok(response) => Ok(Some(response.json().await?)),
+            Err(e) if e.downcast_ref::<ApiError>().is_some_and(|e| e.status == StatusCode::NOT_FOUND) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// All pages of a list.
+    async fn list<T: DeserializeOwned>(&self, path: &str, api_version: &str) -> anyhow::Result<Vec<T>> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Page<T> {
+            value: Vec<T>,
+            next_link: Option<String>,
+        }
+        let Some(mut page) = self.get_optional::<Page<T>>(path, api_version).await? else {
+            return Ok(vec![]);
+        };
+        let mut items = page.value;
+        while let Some(next_link) = page.next_link {
+            // Absolute, including api-version
+            page = self.send_url(Method::GET, &next_link, None).await?.json().await?;
+            items.append(&mut page.value);
+        }
+        Ok(items)
+    }
+
+    /// Waits until the resource's provisioning state is Succeeded.
+    async fn wait_provisioned(&self, path: &str, api_version: &str) -> anyhow::Result<()> {
+        #[derive(Deserialize)]
+        struct Resource {
+            #[serde(default)]
+            properties: Properties,
+        }
+        #[derive(Deserialize, Default)]
+        #[serde(rename_all = "camelCase")]
+        struct Properties {
+            provisioning_state: Option<String>,
+        }
+        loop {
+            let resource: Resource = self.get(path, api_version).await?;
+            match resource.properties.provisioning_state.as_deref() {
+                None | Some("Succeeded") => return Ok(()),
+                Some(state @ ("Failed" | "Canceled")) => anyhow::bail!("provisioning {path}: {state}"),
+                Some(state) => {
+                    log::debug!("{path}: {state}");
+                    sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    /// Starts creating or updating the resource, does not wait for provisioning to complete, see `wait_provisioned`.
+    async fn put(&self, path: &str, api_version: &str, body: serde_json::Value) -> anyhow::Result<()> {
+        self.send(Method::PUT, path, api_version, Some(body)).await?;
+        Ok(())
+    }
+
+    /// Starts deleting the resource, does not wait for deletion to complete.
+    async fn delete(&self, path: &str, api_version: &str) -> anyhow::Result<()> {
+        self.send(Method::DELETE, path, api_version, None).await?;
+        Ok(())
+    }
+
+    async fn send(&self, method: Method, path: &str, api_version: &str, body: Option<serde_json::Value>) -> anyhow::Result<reqwest::Response> {
+        let separator = if path.contains('?') { '&' } else { '?' };
+        self.send_url(method, &format!("{MANAGEMENT_URL}{path}{separator}api-version={api_version}"), body)
+            .await
+    }
+
+    async fn send_url(&self, method: Method, url: &str, body: Option<serde_json::Value>) -> anyhow::Result<reqwest::Response> {
+        let path = url.strip_prefix(MANAGEMENT_URL).unwrap_or(url);
+        log::debug!("{method} {path}");
+        let mut request = self.http.request(method.clone(), url).bearer_auth(&self.token);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            #[derive(Deserialize)]
+            struct ErrorResponse {
+                error: ErrorDetail,
+            }
+            #[derive(Deserialize)]
+            struct ErrorDetail {
+                code: String,
+                message: String,
+            }
+            let status = response.status();
+            let message = match response.json::<ErrorResponse>().await {
+                Ok(ErrorResponse { error }) => format!("{method} {path} failed: {} ({})", error.message, error.code),
+                Err(_) => format!("{method} {path} failed: {status}"),
+            };
+            return Err(ApiError { status, message }.into());
+        }
+        Ok(response)
+    }
+}
+
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+trait VecExt<T> {
+    fn one(self, what: &str) -> anyhow::Result<T>;
+}
+
+impl<T> VecExt<T> for Vec<T> {
+    fn one(self, what: &str) -> anyhow::Result<T> {
+        let len = self.len();
+        let mut items = self.into_iter();
+        match (items.next(), items.next()) {
+            (Some(item), None) => Ok(item),
+            _ => anyhow::bail!("expected exactly one {what}, got {len}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_references() {
+        let reference = |release: &str, arm64, gen2| {
+            let (offer, sku) = image_reference(release.parse().unwrap(), arm64, gen2).unwrap();
+            format!("{offer} {sku}")
+        };
+        assert_eq!(reference("24.04", false, true), "ubuntu-24_04-lts server");
+        assert_eq!(reference("24.04", true, true), "ubuntu-24_04-lts server-arm64");
+        assert_eq!(reference("24.10", false, false), "ubuntu-24_10 server-gen1");
+        assert_eq!(reference("22.04", false, true), "0001-com-ubuntu-server-jammy 22_04-lts-gen2");
+        assert_eq!(reference("22.04", true, true), "0001-com-ubuntu-server-jammy 22_04-lts-arm64");
+        assert_eq!(reference("22.04", false, false), "0001-com-ubuntu-server-jammy 22_04-lts");
+    }
+}
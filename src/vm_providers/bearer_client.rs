@@ -0,0 +1,47 @@
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use std::{fmt, marker::PhantomData};
+
+/// Client of a JSON API authenticating with a bearer token, like Hetzner Cloud's and DigitalOcean's.
+///
+/// Error responses are decoded as `E` for the error message, if they can be.
+pub struct BearerClient<E> {
+    http: reqwest::Client,
+    api_url: &'static str,
+    token: String,
+    error: PhantomData<fn() -> E>,
+}
+
+impl<E: DeserializeOwned + fmt::Display> BearerClient<E> {
+    pub fn new(api_url: &'static str, token: String) -> Self {
+        Self { http: reqwest::Client::new(), api_url, token, error: PhantomData }
+    }
+
+    pub async fn request<T: DeserializeOwned>(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> anyhow::Result<T> {
+        let response = self.send(method, path, body).await?;
+        Ok(response.json().await?)
+    }
+
+    /// For endpoints responding with `204 No Content`.
+    pub async fn request_no_content(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> anyhow::Result<()> {
+        self.send(method, path, body).await?;
+        Ok(())
+    }
+
+    async fn send(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> anyhow::Result<reqwest::Response> {
+        log::debug!("{method} {path}");
+        let mut request = self.http.request(method.clone(), format!("{}{path}", self.api_url)).bearer_auth(&self.token);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            match response.json::<E>().await {
+                Ok(error) => anyhow::bail!("{method} {path} failed: {error}"),
+                Err(_) => anyhow::bail!("{method} {path} failed: {status}"),
+            }
+        }
+        Ok(response)
+    }
+}
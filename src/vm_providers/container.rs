@@ -0,0 +1,336 @@
+use super::{instance_name, DestroyGuard, SpawnedVm, VmAddress, VmProvider};
+use crate::{steps, unix_http, worker::connect_serving};
+use async_trait::async_trait;
+use clap::Args;
+use hyper::{body::Bytes, Method, StatusCode};
+use reqwest::Url;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    env,
+    net::Ipv4Addr,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+use tokio::time::sleep;
+
+const LABEL: &str = "fleeting";
+
+/// Local Docker or Podman container
+#[derive(Args, Clone)]
+#[command(
+    override_usage = color_print::cstr! {r#"<bold>fleeting</bold> <bold>container</bold> [OPTIONS] [COMMAND]...
+
+Runs a privileged container standing in for a VM: its main process installs
+and starts sshd (unless the image already has it) and then runs the user_data.
+/var/lib/docker is an anonymous volume.
+
+Mostly useful for testing fleeting itself, or where nested dockerd is wanted.
+
+<bold><underline>Requirements:</underline></bold>
+  - Access to the Docker or Podman API socket (see '--socket')
+  - Privileged containers allowed (for Podman, run it as root)
+
+"#},)]
+pub struct Container {
+    /// [default: DOCKER_HOST if unix://, or the first existing of /var/run/docker.sock, $XDG_RUNTIME_DIR/podman/podman.sock, /run/podman/podman.sock]
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    /// Ubuntu-based image. Pre-installing openssh-server, curl and iptables speeds up startup.
+    #[arg(long, default_value = "ubuntu:24.04")]
+    image: String,
+
+    /// Publish ports on 127.0.0.1 instead of connecting to the container's IP.
+    ///
+    /// Needed when containers run in a VM, e.g. with Docker Desktop.
+    #[arg(long, default_value_t = !cfg!(target_os = "linux"))]
+    publish: bool,
+}
+
+#[async_trait]
+impl VmProvider for Container {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
+        let step = steps::start();
+        log::info!("Connecting to container engine...");
+        let client = {
+            let socket_path = match &self.socket {
+                Some(path) => path.clone(),
+                None => default_socket_path()?,
+            };
+            let client = Client { socket_path };
+
+            #[derive(Deserialize)]
+            #[serde(rename_all = "PascalCase")]
+            struct Version {
+                version: String,
+                #[serde(default)]
+                components: Vec<Component>,
+            }
+            #[derive(Deserialize)]
+            #[serde(rename_all = "PascalCase")]
+            struct Component {
+                name: String,
+            }
+            let version: Version = client.request(Method::GET, "/version", None).await?;
+            let engine = version.components.first().map(|c| c.name.as_str()).unwrap_or("Engine");
+            log::info!("{engine} {}", version.version);
+            client
+        };
+
+        let step = step.next();
+        log::info!("Purging old stopped fleeting containers...");
+        {
+            #[derive(Deserialize)]
+            #[serde(rename_all = "PascalCase")]
+            struct ListedContainer {
+                id: String,
+            }
+            let filters = json!({ "label": [LABEL], "status": ["created", "exited", "dead"] });
+            let path = with_query("/containers/json", &[("all", "true"), ("filters", &filters.to_string())])?;
+            let stopped: Vec<ListedContainer> = client.request(Method::GET, &path, None).await?;
+            for container in &stopped {
+                client.remove(&container.id).await?;
+            }
+            log::info!("{} purged", stopped.len());
+        }
+
+        let step = step.next();
+        log::info!("Pulling image if needed...");
+        {
+            let (status, _) = client.send(Method::GET, &format!("/images/{}/json", self.image), None).await?;
+            if status == StatusCode::NOT_FOUND {
+                let (status, body) = client
+                    .send(Method::POST, &with_query("/images/create", &[("fromImage", &self.image)])?, None)
+                    .await?;
+                // Progress is streamed as JSON lines, failures too
+                let error = String::from_utf8_lossy(&body)
+                    .lines()
+                    .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+                    .find_map(|progress| progress["error"].as_str().map(str::to_owned));
+                if let Some(error) = error.or((!status.is_success()).then(|| status.to_string())) {
+                    anyhow::bail!("pulling {} failed: {error}", self.image);
+                }
+                log::info!("{} (pulled)", self.image);
+            } else {
+                log::info!("{} (already present)", self.image);
+            }
+        }
+
+        let step = step.next();
+        log::info!("Launching a container...");
+        let name = instance_name();
+        let id = {
+            let mut body = json!({
+                "Image": self.image,
+                "Cmd": ["bash", "-c", include_str!("container_bootstrap.sh"), "bootstrap", user_data],
+                "Labels": { LABEL: "", "fleeting-instance": name },
+                // overlay2 does not work on top of the container's overlayfs
+                "Volumes": { "/var/lib/docker": {} },
+                "HostConfig": { "Privileged": true },
+            });
+            if self.publish {
+                let host_port = json!([{ "HostIp": "127.0.0.1", "HostPort": "" }]);
+                body["ExposedPorts"] = json!({ "22/tcp": {}, "2376/tcp": {} });
+                body["HostConfig"]["PortBindings"] = json!({ "22/tcp": host_port, "2376/tcp": host_port });
+            }
+
+            #[derive(Deserialize)]
+            #[serde(rename_all = "PascalCase")]
+            struct Created {
+                id: String,
+            }
+            let created: Created = client
+                .request(Method::POST, &with_query("/containers/create", &[("name", &name)])?, Some(body))
+                .await?;
+            created.id
+        };
+        log::info!("{name}");
+
+        // From here on, the container must be removed when the run ends
+        let remove = {
+            let (client, id) = (client.clone(), id.clone());
+            DestroyGuard::new(async move { client.remove(&id).await })
+        };
+        let started = async {
+            client.request_no_content(Method::POST, &format!("/containers/{id}/start"), None).await?;
+
+            let step = step.next();
+            log::info!("Waiting for sshd to start...");
+            let address = {
+                let inspected: Inspected = client.request(Method::GET, &format!("/containers/{id}/json"), None).await?;
+                let address = if self.publish {
+                    let host_port = |port: &str| -> anyhow::Result<u16> {
+                        let binding = inspected.network_settings.ports.get(port).and_then(|bindings| bindings.as_ref()?.first());
+                        Ok(binding.ok_or(anyhow::format_err!("{port} not published"))?.host_port.parse()?)
+                    };
+                    VmAddress { ip: Ipv4Addr::LOCALHOST, ssh_port: host_port("22/tcp")?, docker_port: host_port("2376/tcp")? }
+                } else {
+                    inspected
+                        .network_settings
+                        .networks
+                        .values()
+                        .find_map(|network| network.ip_address.parse::<Ipv4Addr>().ok())
+                        .ok_or(anyhow::format_err!("container has no IPv4 address"))?
+                        .into()
+                };
+
+                // The bootstrap may need to install packages first, which takes longer than the worker waits for.
+                let deadline = SystemTime::now() + Duration::from_secs(300);
+                loop {
+                    if SystemTime::now() > deadline {
+                        anyhow::bail!("sshd did not start in time limit");
+                    }
+                    // With --publish, docker-proxy accepts before sshd listens, so wait for sshd's banner
+                    match connect_serving(address.ssh(), Some(b"SSH-")).await {
+                        Ok(_) => break address,
+                        Err(e) => log::debug!("{e:#}"),
+                    }
+                    let inspected: Inspected = client.request(Method::GET, &format!("/containers/{id}/json"), None).await?;
+                    if !inspected.state.running {
+                        anyhow::bail!("container exited with code {}:\n{}", inspected.state.exit_code, client.logs_tail(&id).await?);
+                    }
+                    sleep(Duration::from_secs(1)).await;
+                }
+            };
+
+            steps::end(step);
+            anyhow::Ok(address)
+        }
+        .await;
+
+        match started {
+            Ok(address) => Ok(SpawnedVm { address, destroy: Some(remove.disarm()), interruption_notice_command: None }),
+            Err(e) => {
+                log::info!("Removing container after failed start...");
+                if let Err(e) = remove.disarm().await {
+                    log::error!("Failed to remove container: {e:#}");
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn teardown_command(&self) -> &str {
+        // The container stops when user_data, its main process, exits
+        "true"
+    }
+}
+
+fn default_socket_path() -> anyhow::Result<PathBuf> {
+    if let Some(path) = env::var("DOCKER_HOST").ok().as_deref().and_then(|host| host.strip_prefix("unix://")) {
+        return Ok(PathBuf::from(path));
+    }
+    let mut candidates = vec![PathBuf::from("/var/run/docker.sock")];
+    if let Some(runtime_dir) = env::var_os("XDG_RUNTIME_DIR") {
+        candidates.push(PathBuf::from(runtime_dir).join("podman/podman.sock"));
+    }
+    candidates.push(PathBuf::from("/run/podman/podman.sock"));
+    candidates
+        .iter()
+        .find(|path| path.exists())
+        .cloned()
+        .ok_or(anyhow::format_err!("no Docker or Podman socket found in: {candidates:?}"))
+}
+
+/// `path?key=value&...`, with values URL-encoded.
+fn with_query(path: &str, pairs: &[(&str, &str)]) -> anyhow::Result<String> {
+    let mut url = Url::parse("http://localhost")?.join(path)?;
+    url.query_pairs_mut().extend_pairs(pairs);
+    Ok(format!("{}?{}", url.path(), url.query().unwrap_or_default()))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Inspected {
+    state: State,
+    network_settings: NetworkSettings,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct State {
+    running: bool,
+    exit_code: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NetworkSettings {
+    #[serde(default)]
+    networks: HashMap<String, Network>,
+    #[serde(default)]
+    ports: HashMap<String, Option<Vec<PortBinding>>>,
+}
+
+#[derive(Deserialize)]
+struct Network {
+    #[serde(rename = "IPAddress")]
+    ip_address: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PortBinding {
+    host_port: String,
+}
+
+#[derive(Clone)]
+struct Client {
+    socket_path: PathBuf,
+}
+
+impl Client {
+    async fn request<T: DeserializeOwned>(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> anyhow::Result<T> {
+        let body = self.send_ok(method, path, body).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// For endpoints responding with `204 No Content`.
+    async fn request_no_content(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> anyhow::Result<()> {
+        self.send_ok(method, path, body).await?;
+        Ok(())
+    }
+
+    async fn send_ok(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> anyhow::Result<Bytes> {
+        let (status, body) = self.send(method.clone(), path, body).await?;
+        if !status.is_success() {
+            #[derive(Deserialize)]
+            struct ApiError {
+                message: String,
+            }
+            match serde_json::from_slice::<ApiError>(&body) {
+                Ok(error) => anyhow::bail!("{method} {path} failed: {}", error.message),
+                Err(_) => anyhow::bail!("{method} {path} failed: {status}"),
+            }
+        }
+        Ok(body)
+    }
+
+    async fn send(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> anyhow::Result<(StatusCode, Bytes)> {
+        unix_http::request(&self.socket_path, method, path, body.as_ref()).await
+    }
+
+    /// Removes the container (running or not) along with its anonymous volumes.
+    async fn remove(&self, id: &str) -> anyhow::Result<()> {
+        self.request_no_content(Method::DELETE, &format!("/containers/{id}?force=true&v=true"), None)
+            .await
+    }
+
+    /// Last lines of stdout and stderr, for error messages.
+    async fn logs_tail(&self, id: &str) -> anyhow::Result<String> {
+        let mut frames = &*self
+            .send_ok(Method::GET, &format!("/containers/{id}/logs?stdout=true&stderr=true&tail=20"), None)
+            .await?;
+        // Without a TTY, output is multiplexed in frames with an 8-byte header ending in the big-endian payload size.
+        let mut logs = Vec::new();
+        while frames.len() >= 8 {
+            let size = u32::from_be_bytes(frames[4..8].try_into().expect("4 bytes")) as usize;
+            let end = frames.len().min(8 + size);
+            logs.extend_from_slice(&frames[8..end]);
+            frames = &frames[end..];
+        }
+        Ok(String::from_utf8_lossy(&logs).trim_end().to_owned())
+    }
+}
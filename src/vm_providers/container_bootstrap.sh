@@ -0,0 +1,24 @@
+#!/bin/bash
+# Main process of containers standing in for VMs. Installs what a cloud image
+# would have (unless the image already does), starts sshd and replaces itself
+# with user_data ($1), so the container stops once user_data exits.
+set -eu -o pipefail
+
+if ! command -v sshd >/dev/null || ! command -v curl >/dev/null || ! command -v iptables >/dev/null; then
+    export DEBIAN_FRONTEND=noninteractive
+    apt-get update -q
+    apt-get install -qy --no-install-recommends openssh-server curl ca-certificates iptables
+fi
+
+# With cgroup v2, move out of the root cgroup so dockerd can enable controllers (as docker:dind does)
+if [ -f /sys/fs/cgroup/cgroup.controllers ]; then
+    mkdir -p /sys/fs/cgroup/init
+    xargs -rn1 </sys/fs/cgroup/cgroup.procs >/sys/fs/cgroup/init/cgroup.procs || :
+    sed -e 's/ / +/g' -e 's/^/+/' </sys/fs/cgroup/cgroup.controllers >/sys/fs/cgroup/cgroup.subtree_control || :
+fi
+
+ssh-keygen -A
+mkdir -p /run/sshd
+/usr/sbin/sshd
+
+exec bash -c "$1"
@@ -0,0 +1,263 @@
+use super::{bearer_client::BearerClient, instance_name, SpawnedVm, VmProvider};
+use crate::{steps, ubuntu_release::UbuntuRelease};
+use anyhow::Context as _;
+use async_trait::async_trait;
+use clap::Args;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::json;
+use std::{env, fmt, net::Ipv4Addr};
+use tokio::time::{sleep, Duration};
+
+const API_URL: &str = "https://api.digitalocean.com/v2";
+const TAG: &str = "fleeting";
+const FIREWALL_NAME: &str = "fleeting";
+
+/// DigitalOcean Droplets
+#[derive(Args, Clone)]
+#[command(
+    override_usage = color_print::cstr! {r#"<bold>fleeting</bold> <bold>digitalocean</bold> [OPTIONS] [COMMAND]...
+
+<bold><underline>Authentication:</underline></bold>
+  - Environment variable DIGITALOCEAN_TOKEN (personal access token with write scope)
+
+<bold><underline>Limitations:</underline></bold>
+Droplets power off, but are not deleted, when shut down. fleeting collects
+garbage at the beginning of the run, but you will be left with a small number
+of powered-off droplets and will continue to pay for them.
+
+"#},)]
+pub struct DigitalOcean {
+    #[arg(long, default_value = "nyc3")]
+    region: String,
+
+    /// Droplet size slug. Determines CPUs, memory and disk size.
+    #[arg(long, default_value = "s-1vcpu-1gb")]
+    size: String,
+
+    /// Disk size, in GiBs. Droplet disks are fixed by the size, so this picks the cheapest size
+    /// in the region with at least this much disk and as many CPUs and as much memory as --size.
+    #[arg(long)]
+    disk: Option<u64>,
+
+    /// Release of Canonical's Ubuntu image.
+    #[arg(long, default_value = "24.04")]
+    ubuntu_release: UbuntuRelease,
+}
+
+#[async_trait]
+impl VmProvider for DigitalOcean {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
+        let step = steps::start();
+        log::info!("Loading DigitalOcean configuration...");
+        let client = {
+            let token = env::var("DIGITALOCEAN_TOKEN").context("DIGITALOCEAN_TOKEN not set")?;
+            Client::new(API_URL, token)
+        };
+
+        let step = step.next();
+        log::info!("Delete powered-off fleeting droplets...");
+        {
+            #[derive(Deserialize)]
+            struct Droplets {
+                droplets: Vec<Droplet>,
+            }
+            let droplets: Droplets = client.request(Method::GET, &format!("/droplets?tag_name={TAG}&per_page=200"), None).await?;
+
+            let orphans = droplets
+                .droplets
+                .into_iter()
+                .filter(|droplet| droplet.name.starts_with("fleeting-") && droplet.status == "off")
+                .collect::<Vec<_>>();
+            for droplet in &orphans {
+                client.request_no_content(Method::DELETE, &format!("/droplets/{}", droplet.id), None).await?;
+            }
+            log::info!("{} deleted", orphans.len());
+        }
+
+        let step = step.next();
+        log::info!("Looking up droplet size...");
+        let size = match self.disk {
+            None => {
+                log::info!("{}", self.size);
+                self.size.clone()
+            }
+            Some(disk) => {
+                #[derive(Deserialize)]
+                struct Sizes {
+                    sizes: Vec<Size>,
+                }
+                #[derive(Deserialize)]
+                struct Size {
+                    slug: String,
+                    memory: u64,
+                    vcpus: u64,
+                    disk: u64,
+                    price_monthly: f64,
+                    available: bool,
+                    regions: Vec<String>,
+                }
+                let sizes: Sizes = client.request(Method::GET, "/sizes?per_page=200", None).await?;
+                let Some(base) = sizes.sizes.iter().find(|size| size.slug == self.size) else {
+                    anyhow::bail!("unknown droplet size: {}", self.size)
+                };
+                let size = sizes
+                    .sizes
+                    .iter()
+                    .filter(|size| size.available && size.regions.contains(&self.region))
+                    .filter(|size| size.disk >= disk && size.vcpus >= base.vcpus && size.memory >= base.memory)
+                    .min_by(|a, b| a.price_monthly.total_cmp(&b.price_monthly))
+                    .with_context(|| format!("no size in {} with at least {disk} GiB disk and the resources of {}", self.region, self.size))?;
+                log::info!("{} ({} GiB disk)", size.slug, size.disk);
+                size.slug.clone()
+            }
+        };
+
+        let step = step.next();
+        log::info!("Creating firewall if needed...");
+        let firewall_id = {
+            #[derive(Deserialize)]
+            struct Firewalls {
+                firewalls: Vec<Firewall>,
+            }
+            #[derive(Deserialize)]
+            struct CreatedFirewall {
+                firewall: Firewall,
+            }
+            #[derive(Deserialize)]
+            struct Firewall {
+                id: String,
+                name: String,
+            }
+            let firewalls: Firewalls = client.request(Method::GET, "/firewalls?per_page=200", None).await?;
+            let matching = firewalls.firewalls.into_iter().filter(|f| f.name == FIREWALL_NAME).collect::<Vec<_>>();
+            match &*matching {
+                [] => {
+                    let everywhere = json!({ "addresses": ["0.0.0.0/0", "::/0"] });
+                    let created: CreatedFirewall = client
+                        .request(
+                            Method::POST,
+                            "/firewalls",
+                            Some(json!({
+                                "name": FIREWALL_NAME,
+                                "inbound_rules": [
+                                    { "protocol": "tcp", "ports": "0", "sources": everywhere },
+                                    { "protocol": "udp", "ports": "0", "sources": everywhere },
+                                    { "protocol": "icmp", "sources": everywhere },
+                                ],
+                                "outbound_rules": [
+                                    { "protocol": "tcp", "ports": "0", "destinations": everywhere },
+                                    { "protocol": "udp", "ports": "0", "destinations": everywhere },
+                                    { "protocol": "icmp", "destinations": everywhere },
+                                ],
+                            })),
+                        )
+                        .await?;
+                    log::info!("{} (created)", created.firewall.id);
+                    created.firewall.id
+                }
+                [firewall] => {
+                    log::info!("{} (already existed)", firewall.id);
+                    firewall.id.clone()
+                }
+                x => anyhow::bail!("{} matching firewalls", x.len()),
+            }
+        };
+
+        let step = step.next();
+        log::info!("Launching a droplet...");
+        let droplet_id = {
+            #[derive(Deserialize)]
+            struct CreatedDroplet {
+                droplet: Droplet,
+            }
+            let created: CreatedDroplet = client
+                .request(
+                    Method::POST,
+                    "/droplets",
+                    Some(json!({
+                        "name": instance_name(),
+                        "region": self.region,
+                        "size": size,
+                        // e.g. ubuntu-24-04-x64
+                        "image": format!("ubuntu-{}-x64", self.ubuntu_release.to_string().replace('.', "-")),
+                        "user_data": user_data,
+                        "tags": [TAG],
+                    })),
+                )
+                .await?;
+            let droplet_id = created.droplet.id;
+
+            client
+                .request_no_content(
+                    Method::POST,
+                    &format!("/firewalls/{firewall_id}/droplets"),
+                    Some(json!({ "droplet_ids": [droplet_id] })),
+                )
+                .await?;
+            droplet_id
+        };
+        log::info!("{droplet_id}");
+
+        let step = step.next();
+        log::info!("Waiting for droplet to start...");
+        let public_ip = {
+            #[derive(Deserialize)]
+            struct GetDroplet {
+                droplet: Droplet,
+            }
+            let droplet = loop {
+                log::debug!("Retrieving droplet status...");
+                let GetDroplet { droplet } = client.request(Method::GET, &format!("/droplets/{droplet_id}"), None).await?;
+                match droplet.status.as_str() {
+                    "new" => sleep(Duration::from_secs(1)).await,
+                    "active" => break droplet,
+                    state => anyhow::bail!("droplet transitioned into state: {state}"),
+                }
+            };
+            droplet
+                .networks
+                .v4
+                .into_iter()
+                .find(|network| network.r#type == "public")
+                .ok_or(anyhow::format_err!("droplet should have a public ipv4"))?
+                .ip_address
+        };
+
+        steps::end(step);
+        Ok(public_ip.into())
+    }
+}
+
+#[derive(Deserialize)]
+struct Droplet {
+    id: u64,
+    name: String,
+    status: String,
+    networks: Networks,
+}
+
+#[derive(Deserialize)]
+struct Networks {
+    v4: Vec<NetworkV4>,
+}
+
+#[derive(Deserialize)]
+struct NetworkV4 {
+    ip_address: Ipv4Addr,
+    r#type: String,
+}
+
+type Client = BearerClient<ApiError>;
+
+#[derive(Deserialize)]
+struct ApiError {
+    id: String,
+    message: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.id)
+    }
+}
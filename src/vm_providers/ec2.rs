@@ -1,18 +1,39 @@
-use super::VmProvider;
-use crate::steps;
+use super::{allowed_source_ranges, instance_name, DestroyGuard, SpawnedVm, VmProvider};
+use crate::{steps, ubuntu_release::UbuntuRelease};
+use anyhow::Context as _;
 use async_trait::async_trait;
 use aws_config::{meta::region::RegionProviderChain, Region};
+use aws_credential_types::provider::ProvideCredentials as _;
 use aws_sdk_ec2::{
     self as ec2,
-    types::{ArchitectureType, BlockDeviceMapping, EbsBlockDevice, InstanceStateName, InstanceType, ResourceType, ShutdownBehavior, Tag, TagSpecification},
+    types::{
+        ArchitectureType, BlockDeviceMapping, EbsBlockDevice, Filter, IamInstanceProfileSpecification, InstanceInterruptionBehavior,
+        InstanceMarketOptionsRequest, InstanceNetworkInterfaceSpecification, InstanceStateName, InstanceType, IpPermission, IpRange, MarketType, Placement,
+        ResourceType, ShutdownBehavior, SpotInstanceType, SpotMarketOptions, Tag, TagSpecification, VolumeType,
+    },
 };
 use aws_sdk_sts::{self as sts};
+use aws_sigv4::{
+    http_request::{sign, SignableBody, SignableRequest, SigningSettings},
+    sign::v4,
+};
 use base64::prelude::*;
 use clap::Args;
-use std::net::Ipv4Addr;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    net::Ipv4Addr,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use tokio::time::{sleep, Duration};
 
-const SECURITY_GROUP_NAME: &str = "fleeting";
+/// Unix time at which a security group was created, so garbage collection can spare those of runs still launching.
+const CREATED_TAG: &str = "fleeting:created";
+
+/// Security group shared by the runs of older versions.
+const LEGACY_SECURITY_GROUP_NAME: &str = "fleeting";
+const LEGACY_SECURITY_GROUP_DESCRIPTION: &str = "fleeting ephemeral instances";
 
 /// AWS Elastic Compute Cloud
 #[derive(Args, Clone)]
@@ -35,20 +56,85 @@ pub struct Ec2 {
     #[arg(long)]
     region: Option<String>,
 
-    #[arg(long, default_value = "t4g.nano")]
-    instance_type: InstanceType,
+    /// Comma-separated, in order of preference. The next one is tried when out of capacity.
+    #[arg(long = "instance-type", value_name = "INSTANCE_TYPE", value_delimiter = ',', default_value = "t4g.nano")]
+    instance_types: Vec<InstanceType>,
+
+    /// AMI ID, or SSM parameter resolving to one (e.g. /golden-images/ubuntu/ami-id) [default: Canonical's Ubuntu for the instance type]
+    #[arg(long, conflicts_with = "ubuntu_release")]
+    image: Option<String>,
+
+    /// Release of Canonical's Ubuntu image.
+    #[arg(long, default_value = "24.04")]
+    ubuntu_release: UbuntuRelease,
+
+    /// Comma-separated, in order of preference. The next one is tried when out of capacity [default: any]
+    #[arg(long = "availability-zone", value_name = "AVAILABILITY_ZONE", value_delimiter = ',')]
+    availability_zones: Vec<String>,
+
+    /// Comma-separated, in order of preference. The next one is tried when out of capacity [default: the VPC's subnets]
+    #[arg(long = "subnet-id", value_name = "SUBNET_ID", value_delimiter = ',', conflicts_with = "availability_zones")]
+    subnet_ids: Vec<String>,
+
+    /// [default: the subnets' VPC, or the default VPC]
+    #[arg(long)]
+    vpc_id: Option<String>,
+
+    /// Connect to the private IP, e.g. when running within the VPC. Also skips the public IP in chosen subnets.
+    #[arg(long)]
+    private_ip: bool,
+
+    /// Comma-separated CIDRs allowed to reach ssh and dockerd [default: this host's public IP, or the VPC with --private-ip]
+    #[arg(long = "allow-cidr", value_name = "CIDR", value_delimiter = ',')]
+    allow_cidrs: Vec<String>,
+
+    /// Instance profile (name or ARN) whose role provides the instance with credentials, e.g. for pulling from ECR.
+    #[arg(long, value_name = "NAME|ARN")]
+    iam_instance_profile: Option<String>,
 
     /// Disk size, in GiBs.
     #[arg(long)]
     disk: Option<usize>,
+
+    /// EBS volume type, e.g. gp3, io2 [default: the image's]
+    #[arg(long)]
+    volume_type: Option<VolumeType>,
+
+    /// Provisioned IOPS, for gp3, io1 and io2 volumes.
+    #[arg(long)]
+    iops: Option<i32>,
+
+    /// Provisioned throughput, in MiB/s, for gp3 volumes.
+    #[arg(long)]
+    throughput: Option<i32>,
+
+    /// Encrypt the volume, with the AWS managed key unless --kms-key-id is given [default: the account's EBS encryption default]
+    #[arg(long)]
+    encrypted: bool,
+
+    /// KMS key (ID, alias or ARN) to encrypt the volume with. Implies --encrypted.
+    #[arg(long)]
+    kms_key_id: Option<String>,
+
+    /// Request a one-time spot instance, terminated when interrupted (failing the run with a distinct error). Falls back to on-demand without spot capacity.
+    #[arg(long)]
+    spot: bool,
+
+    /// Maximum hourly spot price, in USD [default: on-demand price]
+    #[arg(long, value_name = "USD", requires = "spot")]
+    spot_max_price: Option<String>,
+
+    /// Fail instead of falling back to on-demand.
+    #[arg(long, requires = "spot")]
+    spot_only: bool,
 }
 
 #[async_trait]
 impl VmProvider for Ec2 {
-    async fn spawn(&self, user_data: &str) -> anyhow::Result<Ipv4Addr> {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
         let step = steps::start();
         log::info!("Loading AWS configuration...");
-        let ec2_client = {
+        let (config, ec2_client) = {
             // TODO: use webpki_roots?
             // let https_connector = hyper_rustls::HttpsConnectorBuilder::new().with_webpki_roots().https_or_http().enable_http1().build();
             // let hyper_client = aws_smithy_runtime::client::http::hyper_014::HyperClientBuilder::new().build(https_connector);
@@ -69,63 +155,245 @@ impl VmProvider for Ec2 {
             let caller_identity = sts_client.get_caller_identity().send().await?;
             log::info!("Identity: {}", caller_identity.arn().expect("arn"));
 
-            ec2::Client::new(&config)
+            let ec2_client = ec2::Client::new(&config);
+            (config, ec2_client)
         };
 
-        let step: _ = step.next();
-        log::info!("Looking up instance type...");
-        let image_id = {
-            let output = ec2_client.describe_instance_types().instance_types(self.instance_type.clone()).send().await?;
-            let instance_type_info = output.instance_types.expect_one("instance_type");
-            let instance_type_archs = instance_type_info
-                .processor_info
-                .expect("processor_info")
-                .supported_architectures
-                .expect("supported_architectures");
-
-            if instance_type_archs.contains(&ArchitectureType::Arm64) {
-                "resolve:ssm:/aws/service/canonical/ubuntu/server/24.04/stable/current/arm64/hvm/ebs-gp3/ami-id"
-            } else if instance_type_archs.contains(&ArchitectureType::X8664) {
-                "resolve:ssm:/aws/service/canonical/ubuntu/server/24.04/stable/current/amd64/hvm/ebs-gp3/ami-id"
-            } else {
-                anyhow::bail!("unsupported instance type architectures: {instance_type_archs:?}")
+        let step = step.next();
+        log::info!("Deleting unused fleeting security groups...");
+        {
+            let output = ec2_client
+                .describe_security_groups()
+                .filters(Filter::builder().name("group-name").values("fleeting-*").build())
+                .filters(Filter::builder().name("tag-key").values(CREATED_TAG).build())
+                .send()
+                .await?;
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            let mut group_ids = vec![];
+            for security_group in output.security_groups() {
+                let created = security_group
+                    .tags()
+                    .iter()
+                    .find(|tag| tag.key() == Some(CREATED_TAG))
+                    .and_then(|tag| tag.value()?.parse::<u64>().ok())
+                    .unwrap_or_default();
+                // Runs create their security group before launching the instance that uses it
+                if now.saturating_sub(created) < 600 {
+                    continue;
+                }
+                group_ids.push(security_group.group_id().expect("group_id").to_owned());
+            }
+            // Older versions shared a single group open to the world, deleted once no instance uses it anymore
+            let output = ec2_client
+                .describe_security_groups()
+                .filters(Filter::builder().name("group-name").values(LEGACY_SECURITY_GROUP_NAME).build())
+                .filters(Filter::builder().name("description").values(LEGACY_SECURITY_GROUP_DESCRIPTION).build())
+                .send()
+                .await?;
+            group_ids.extend(
+                output
+                    .security_groups()
+                    .iter()
+                    .map(|security_group| security_group.group_id().expect("group_id").to_owned()),
+            );
+
+            let mut deleted = 0;
+            for group_id in &group_ids {
+                match ec2_client.delete_security_group().group_id(group_id).send().await {
+                    Ok(_) => deleted += 1,
+                    Err(e) => match e.as_service_error().and_then(|e| e.meta().code()) {
+                        Some("DependencyViolation") => log::debug!("{group_id} still in use"),
+                        // Deleted by a concurrent run
+                        Some("InvalidGroup.NotFound") => log::debug!("{group_id} already deleted"),
+                        _ => anyhow::bail!(e),
+                    },
+                }
+            }
+            log::info!("{deleted} deleted");
+        }
+
+        let step = step.next();
+        log::info!("Looking up instance types and images...");
+        // Preference order, each with the image (for its architecture)
+        let candidates = {
+            let mut images: HashMap<String, Image> = HashMap::new();
+            let output = ec2_client
+                .describe_instance_types()
+                .set_instance_types(Some(self.instance_types.clone()))
+                .send()
+                .await?;
+            let instance_type_infos = output.instance_types.unwrap_or_default();
+            let mut candidates = vec![];
+            for instance_type in &self.instance_types {
+                let Some(instance_type_info) = instance_type_infos.iter().find(|info| info.instance_type() == Some(instance_type)) else {
+                    anyhow::bail!("instance type not found: {instance_type}")
+                };
+                let instance_type_archs = instance_type_info.processor_info().expect("processor_info").supported_architectures();
+
+                let image_id = match &self.image {
+                    Some(image) if image.starts_with('/') => get_ssm_parameter(&config, image).await?,
+                    Some(image) => image.clone(),
+                    None => {
+                        let arch = if instance_type_archs.contains(&ArchitectureType::Arm64) {
+                            "arm64"
+                        } else if instance_type_archs.contains(&ArchitectureType::X8664) {
+                            "amd64"
+                        } else {
+                            anyhow::bail!("unsupported {instance_type} architectures: {instance_type_archs:?}")
+                        };
+                        // Releases before 24.04 are only published with gp2 root volumes
+                        let volume_type = if self.ubuntu_release >= UbuntuRelease::new(24, 4) {
+                            "ebs-gp3"
+                        } else {
+                            "ebs-gp2"
+                        };
+                        let parameter = format!(
+                            "/aws/service/canonical/ubuntu/server/{}/stable/current/{arch}/hvm/{volume_type}/ami-id",
+                            self.ubuntu_release
+                        );
+                        get_ssm_parameter(&config, &parameter).await?
+                    }
+                };
+                // The root volume's device name differs between images, e.g. /dev/sda1 or /dev/xvda
+                if !images.contains_key(&image_id) {
+                    let output = ec2_client.describe_images().image_ids(&image_id).send().await?;
+                    let image = output
+                        .images
+                        .unwrap_or_default()
+                        .into_iter()
+                        .next()
+                        .with_context(|| format!("image not found: {image_id}"))?;
+                    let root_device_name = image.root_device_name.with_context(|| format!("{image_id} has no root device"))?;
+                    log::debug!("{image_id}: root device {root_device_name}");
+                    images.insert(image_id.clone(), Image { id: image_id.clone(), root_device_name });
+                }
+                candidates.push((instance_type.clone(), images[&image_id].clone()));
             }
+            candidates
         };
 
-        let step: _ = step.next();
-        log::info!("Creating security group if needed...");
+        let step = step.next();
+        log::info!("Looking up subnets...");
+        let (vpc_id, default_vpc, locations) = {
+            let subnets = if !self.subnet_ids.is_empty() {
+                let output = ec2_client.describe_subnets().set_subnet_ids(Some(self.subnet_ids.clone())).send().await?;
+                let mut subnets = output.subnets.unwrap_or_default();
+                subnets.sort_by_key(|subnet| self.subnet_ids.iter().position(|id| subnet.subnet_id() == Some(id)));
+                subnets
+            } else if let Some(vpc_id) = &self.vpc_id {
+                let mut request = ec2_client.describe_subnets().filters(Filter::builder().name("vpc-id").values(vpc_id).build());
+                if !self.availability_zones.is_empty() {
+                    request = request.filters(
+                        Filter::builder()
+                            .name("availability-zone")
+                            .set_values(Some(self.availability_zones.clone()))
+                            .build(),
+                    );
+                }
+                let mut subnets = request.send().await?.subnets.unwrap_or_default();
+                subnets.sort_by_key(|subnet| self.availability_zones.iter().position(|zone| subnet.availability_zone() == Some(zone)));
+                if subnets.is_empty() {
+                    anyhow::bail!("no matching subnets in {vpc_id}");
+                }
+                subnets
+            } else {
+                vec![]
+            };
+
+            let mut vpc_ids = subnets.iter().map(|subnet| subnet.vpc_id().expect("vpc_id")).collect::<Vec<_>>();
+            vpc_ids.sort();
+            vpc_ids.dedup();
+            let vpc_id = match (vpc_ids.as_slice(), &self.vpc_id) {
+                ([], _) => None,
+                ([vpc_id], None) => Some(vpc_id.to_string()),
+                ([vpc_id], Some(expected)) if vpc_id == expected => Some(vpc_id.to_string()),
+                (vpc_ids, _) => anyhow::bail!("subnets are not all in the same VPC: {}", vpc_ids.join(", ")),
+            };
+
+            let locations = if subnets.is_empty() {
+                match self.availability_zones.as_slice() {
+                    [] => vec![Location::default()],
+                    zones => zones
+                        .iter()
+                        .map(|zone| Location { availability_zone: Some(zone.clone()), ..<_>::default() })
+                        .collect(),
+                }
+            } else {
+                subnets
+                    .into_iter()
+                    .map(|subnet| Location { subnet_id: subnet.subnet_id, availability_zone: subnet.availability_zone })
+                    .collect::<Vec<_>>()
+            };
+
+            // Without a subnet, instances launch into the default VPC, which accounts may not have
+            let default_vpc = match &vpc_id {
+                Some(vpc_id) => {
+                    log::info!("{vpc_id}: {}", locations.iter().map(Location::to_string).collect::<Vec<_>>().join(", "));
+                    None
+                }
+                None => {
+                    let output = ec2_client
+                        .describe_vpcs()
+                        .filters(Filter::builder().name("is-default").values("true").build())
+                        .send()
+                        .await?;
+                    let vpc = output
+                        .vpcs
+                        .unwrap_or_default()
+                        .into_iter()
+                        .next()
+                        .context("no default VPC, pass --vpc-id or --subnet-id")?;
+                    log::info!("Default VPC ({})", vpc.vpc_id().unwrap_or_default());
+                    Some(vpc)
+                }
+            };
+            (vpc_id, default_vpc, locations)
+        };
+
+        let step = step.next();
+        log::info!("Creating security group...");
         let security_group_id = {
-            let (id, created) = get_or_create_security_group(ec2_client.clone()).await?;
-            if created {
-                log::info!("{id} (created)");
+            let source_ranges = if self.allow_cidrs.is_empty() && self.private_ip {
+                let vpc = match (&vpc_id, default_vpc) {
+                    (Some(vpc_id), _) => {
+                        let output = ec2_client.describe_vpcs().vpc_ids(vpc_id).send().await?;
+                        output
+                            .vpcs
+                            .unwrap_or_default()
+                            .into_iter()
+                            .next()
+                            .with_context(|| format!("VPC not found: {vpc_id}"))?
+                    }
+                    (None, Some(default_vpc)) => default_vpc,
+                    (None, None) => unreachable!("default VPC looked up"),
+                };
+                vec![vpc.cidr_block.context("VPC has no IPv4 CIDR block")?]
             } else {
-                log::info!("{id} (already existed)");
-            }
+                allowed_source_ranges(&self.allow_cidrs).await?
+            };
+            let name = instance_name();
+            let id = create_security_group(&ec2_client, &name, vpc_id.as_deref(), &source_ranges).await?;
+            log::info!("{id} ({name}, allowing {})", source_ranges.join(", "));
             id
         };
 
-        let step: _ = step.next();
+        let step = step.next();
         log::info!("Launching an instance...");
-        let instance_id = {
+        let launched = async {
             // TODO: disk size. here? in global?
-            let output = ec2_client
+            let root_volume = EbsBlockDevice::builder()
+                .delete_on_termination(true)
+                .set_volume_size(self.disk.map(|n| n.try_into().expect("valid disk size")))
+                .set_volume_type(self.volume_type.clone())
+                .set_iops(self.iops)
+                .set_throughput(self.throughput)
+                .set_encrypted((self.encrypted || self.kms_key_id.is_some()).then_some(true))
+                .set_kms_key_id(self.kms_key_id.clone())
+                .build();
+            let base_request = ec2_client
                 .run_instances()
-                .image_id(image_id)
-                .instance_type(self.instance_type.clone())
                 .user_data(BASE64_STANDARD.encode(user_data))
                 .instance_initiated_shutdown_behavior(ShutdownBehavior::Terminate)
-                .security_group_ids(security_group_id)
-                .block_device_mappings(
-                    BlockDeviceMapping::builder()
-                        .device_name("/dev/sda1")
-                        .ebs(
-                            EbsBlockDevice::builder()
-                                .delete_on_termination(true)
-                                .set_volume_size(self.disk.map(|n| n.try_into().expect("valid disk size")))
-                                .build(),
-                        )
-                        .build(),
-                )
                 .tag_specifications(
                     TagSpecification::builder()
                         .resource_type(ResourceType::Instance)
@@ -138,18 +406,147 @@ impl VmProvider for Ec2 {
                         .tags(Tag::builder().key("Name").value("fleeting").build())
                         .build(),
                 )
+                .set_iam_instance_profile(self.iam_instance_profile.as_ref().map(|profile| {
+                    if profile.starts_with("arn:") {
+                        IamInstanceProfileSpecification::builder().arn(profile).build()
+                    } else {
+                        IamInstanceProfileSpecification::builder().name(profile).build()
+                    }
+                }))
                 .min_count(1)
-                .max_count(1)
-                .send()
-                .await?;
+                .max_count(1);
+
+            let request_for = |instance_type: &InstanceType, image: &Image, location: &Location, spot: bool| {
+                let mut request = base_request
+                    .clone()
+                    .image_id(&image.id)
+                    .block_device_mappings(
+                        BlockDeviceMapping::builder()
+                            .device_name(&image.root_device_name)
+                            .ebs(root_volume.clone())
+                            .build(),
+                    )
+                    .instance_type(instance_type.clone());
+                request = match &location.subnet_id {
+                    // Only a network interface can opt out of the subnet's public IP setting
+                    Some(subnet_id) if self.private_ip => request.network_interfaces(
+                        InstanceNetworkInterfaceSpecification::builder()
+                            .device_index(0)
+                            .subnet_id(subnet_id)
+                            .groups(&security_group_id)
+                            .associate_public_ip_address(false)
+                            .delete_on_termination(true)
+                            .build(),
+                    ),
+                    Some(subnet_id) => request.subnet_id(subnet_id).security_group_ids(&security_group_id),
+                    None => request.security_group_ids(&security_group_id).set_placement(
+                        location
+                            .availability_zone
+                            .as_ref()
+                            .map(|zone| Placement::builder().availability_zone(zone).build()),
+                    ),
+                };
+                if spot {
+                    request = request.instance_market_options(
+                        InstanceMarketOptionsRequest::builder()
+                            .market_type(MarketType::Spot)
+                            .spot_options(
+                                SpotMarketOptions::builder()
+                                    .spot_instance_type(SpotInstanceType::OneTime)
+                                    .instance_interruption_behavior(InstanceInterruptionBehavior::Terminate)
+                                    .set_max_price(self.spot_max_price.clone())
+                                    .build(),
+                            )
+                            .build(),
+                    );
+                }
+                request
+            };
+
+            if let Some(profile) = &self.iam_instance_profile {
+                log::debug!("Checking permission to pass the role of {profile}...");
+                // Dry runs check permissions, including iam:PassRole for the profile's role, without launching anything
+                let (instance_type, image) = &candidates[0];
+                match request_for(instance_type, image, &locations[0], false).dry_run(true).send().await {
+                    Ok(_) => anyhow::bail!("dry run of launching with {profile} unexpectedly succeeded"),
+                    Err(e) => match e.as_service_error().and_then(|e| e.meta().code()) {
+                        Some("DryRunOperation") => {}
+                        Some("UnauthorizedOperation") => anyhow::bail!(
+                            "not allowed to launch instances with {profile} (the denied action may be iam:PassRole for its role, or any other one RunInstances needs): {} (decode with 'aws sts decode-authorization-message')",
+                            e.as_service_error().and_then(|e| e.meta().message()).unwrap_or_default()
+                        ),
+                        _ => anyhow::bail!(e),
+                    },
+                }
+            }
 
-            output.instances.expect_one("instance").instance_id.expect("instance_id")
+            // Spot is tried with every instance type and zone before falling back to on-demand
+            let markets: &[bool] = match (self.spot, self.spot_only) {
+                (false, _) => &[false],
+                (true, false) => &[true, false],
+                (true, true) => &[true],
+            };
+            let mut last_error = None;
+            let (output, spot) = 'launch: {
+                for &spot in markets {
+                    for (instance_type, image) in &candidates {
+                        for location in &locations {
+                            match request_for(instance_type, image, location, spot).send().await {
+                                Ok(output) => break 'launch (output, spot),
+                                Err(e) => match e.as_service_error().and_then(|e| e.meta().code()) {
+                                    // `Unsupported`: instance type not offered in the zone
+                                    Some("InsufficientInstanceCapacity" | "SpotMaxPriceTooLow" | "Unsupported") => {
+                                        log::warn!(
+                                            "No {} capacity for {instance_type} in {location}: {}",
+                                            if spot { "spot" } else { "on-demand" },
+                                            e.as_service_error().and_then(|e| e.meta().message()).unwrap_or_default()
+                                        );
+                                        last_error = Some(e);
+                                    }
+                                    _ => anyhow::bail!(e),
+                                },
+                            }
+                        }
+                    }
+                }
+                let e = last_error.expect("at least one launch attempted");
+                return Err(anyhow::Error::new(e).context("no capacity for any of the instance types and locations"));
+            };
+
+            let instance = output.instances.expect_one("instance");
+            let instance_id = instance.instance_id.expect("instance_id");
+            log::info!(
+                "{instance_id} ({}, {})",
+                instance.instance_type.expect("instance_type"),
+                if spot { "spot" } else { "on-demand" }
+            );
+            Ok((instance_id, spot))
+        }
+        .await;
+        let (instance_id, spot) = match launched {
+            Ok(launched) => launched,
+            Err(e) => {
+                log::info!("Deleting security group after failed launch...");
+                if let Err(e) = delete_security_group(&ec2_client, &security_group_id).await {
+                    log::error!("Failed to delete security group: {e:#}");
+                }
+                return Err(e);
+            }
+        };
+
+        let destroy = {
+            let ec2_client = ec2_client.clone();
+            let instance_id = instance_id.clone();
+            let security_group_id = security_group_id.clone();
+            DestroyGuard::new(async move {
+                ec2_client.terminate_instances().instance_ids(&instance_id).send().await?;
+                delete_security_group(&ec2_client, &security_group_id).await
+            })
         };
-        log::info!("{instance_id}");
 
-        let step: _ = step.next();
+        let step = step.next();
         log::info!("Waiting for instance to start...");
-        let public_ip = {
+        let started = async {
             let instance = loop {
                 log::debug!("Retrieving instance status...");
                 let output = match ec2_client.describe_instances().instance_ids(&instance_id).send().await {
@@ -172,11 +569,56 @@ impl VmProvider for Ec2 {
                     state => anyhow::bail!("instance transitioned into state: {state}"),
                 }
             };
-            instance.public_ip_address.expect("public_ip").parse().expect("valid ipv4")
-        };
+            let ip = if self.private_ip {
+                instance.private_ip_address.expect("private_ip")
+            } else {
+                instance.public_ip_address.context("instance has no public IP (see --private-ip)")?
+            };
+            let ip: Ipv4Addr = ip.parse().expect("valid ipv4");
+            Ok(ip)
+        }
+        .await;
 
         steps::end(step);
-        Ok(public_ip)
+        match started {
+            Ok(ip) => Ok(SpawnedVm {
+                destroy: Some(destroy.disarm()),
+                interruption_notice_command: spot.then_some(include_str!("ec2_spot_interruption.sh")),
+                ..ip.into()
+            }),
+            Err(e) => {
+                log::info!("Terminating instance after failed start...");
+                if let Err(e) = destroy.disarm().await {
+                    log::error!("Failed to terminate instance: {e:#}");
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+/// AMI to launch, with where it expects the root volume.
+#[derive(Clone)]
+struct Image {
+    id: String,
+    root_device_name: String,
+}
+
+/// Subnet and/or availability zone to launch in. Neither means any zone of the default VPC.
+#[derive(Default)]
+struct Location {
+    subnet_id: Option<String>,
+    availability_zone: Option<String>,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.subnet_id, &self.availability_zone) {
+            (Some(subnet_id), Some(zone)) => write!(f, "{subnet_id} ({zone})"),
+            (Some(subnet_id), None) => write!(f, "{subnet_id}"),
+            (None, Some(zone)) => write!(f, "{zone}"),
+            (None, None) => write!(f, "any zone"),
+        }
     }
 }
 
@@ -192,48 +634,108 @@ impl<T> OptionVecExt<T> for Option<Vec<T>> {
     }
 }
 
-async fn create_security_group(ec2_client: ec2::Client) -> Result<String, anyhow::Error> {
+/// Allows ssh and dockerd from `source_ranges` only, tagged for garbage collection.
+async fn create_security_group(ec2_client: &ec2::Client, name: &str, vpc_id: Option<&str>, source_ranges: &[String]) -> anyhow::Result<String> {
+    let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
     let output = ec2_client
         .create_security_group()
-        .group_name(SECURITY_GROUP_NAME)
-        .description("fleeting ephemeral instances")
+        .group_name(name)
+        .description("fleeting ephemeral instance")
+        .set_vpc_id(vpc_id.map(str::to_owned))
+        .tag_specifications(
+            TagSpecification::builder()
+                .resource_type(ResourceType::SecurityGroup)
+                .tags(Tag::builder().key(CREATED_TAG).value(created.to_string()).build())
+                .build(),
+        )
         .send()
         .await?;
-    let group_id = output.group_id().unwrap();
+    let group_id = output.group_id().expect("group_id").to_owned();
 
-    ec2_client
+    let ip_ranges = source_ranges.iter().map(|cidr| IpRange::builder().cidr_ip(cidr).build()).collect::<Vec<_>>();
+    let result = ec2_client
         .authorize_security_group_ingress()
-        .group_id(group_id)
-        .ip_protocol("-1")
-        .cidr_ip("0.0.0.0/0")
+        .group_id(&group_id)
+        .set_ip_permissions(Some(
+            [22, 2376]
+                .into_iter()
+                .map(|port| {
+                    IpPermission::builder()
+                        .ip_protocol("tcp")
+                        .from_port(port)
+                        .to_port(port)
+                        .set_ip_ranges(Some(ip_ranges.clone()))
+                        .build()
+                })
+                .collect(),
+        ))
         .send()
-        .await?;
+        .await;
+    if let Err(e) = result {
+        delete_security_group(ec2_client, &group_id).await?;
+        anyhow::bail!(e);
+    }
 
-    Ok(group_id.to_string())
+    Ok(group_id)
 }
 
-async fn get_or_create_security_group(ec2_client: ec2::Client) -> Result<(String, bool), anyhow::Error> {
-    let result = ec2_client.describe_security_groups().group_names(SECURITY_GROUP_NAME).send().await;
-
-    match result {
-        Ok(output) => match output.security_groups() {
-            [] => {
-                // Not sure if this can really happen. I assume `describe_security_groups` would always fail, if SGs don't exist.
-                let group_id = create_security_group(ec2_client).await?;
-                Ok((group_id, true))
-            }
-            [sg] => {
-                let group_id = sg.group_id().unwrap().to_owned();
-                Ok((group_id, false))
+/// Retries while the group is still attached to an instance that is terminating.
+async fn delete_security_group(ec2_client: &ec2::Client, group_id: &str) -> anyhow::Result<()> {
+    let mut attempts = 0;
+    loop {
+        match ec2_client.delete_security_group().group_id(group_id).send().await {
+            Ok(_) => return Ok(()),
+            Err(e) if e.as_service_error().and_then(|e| e.meta().code()) == Some("DependencyViolation") && attempts < 60 => {
+                log::debug!("{group_id} still in use");
+                attempts += 1;
+                sleep(Duration::from_secs(5)).await;
             }
-            x => anyhow::bail!("{} matching security groups", x.len()),
-        },
-        Err(e) => match e.as_service_error().and_then(|e| e.meta().code()) {
-            Some("InvalidGroup.NotFound") => {
-                let group_id = create_security_group(ec2_client).await?;
-                Ok((group_id, true))
-            }
-            _ => anyhow::bail!("error while describing security groups: {:#}", e),
-        },
+            Err(e) => anyhow::bail!(e),
+        }
+    }
+}
+
+/// Value of an SSM parameter, e.g. the AMI ID Canonical publishes for a release.
+/// There is no SSM SDK among the dependencies, so the JSON API is called directly.
+async fn get_ssm_parameter(config: &aws_config::SdkConfig, name: &str) -> anyhow::Result<String> {
+    log::debug!("Resolving SSM parameter {name}...");
+    let region = config.region().context("no region")?.to_string();
+    let credentials = config.credentials_provider().context("no credentials provider")?.provide_credentials().await?;
+    let url = format!("https://ssm.{region}.amazonaws.com/");
+    let body = serde_json::json!({ "Name": name }).to_string();
+    let headers = [("content-type", "application/x-amz-json-1.1"), ("x-amz-target", "AmazonSSM.GetParameter")];
+
+    let identity = credentials.into();
+    let signing_params = v4::SigningParams::builder()
+        .identity(&identity)
+        .region(&region)
+        .name("ssm")
+        .time(SystemTime::now())
+        .settings(SigningSettings::default())
+        .build()?
+        .into();
+    let signable_request = SignableRequest::new("POST", &url, headers.into_iter(), SignableBody::Bytes(body.as_bytes()))?;
+    let (signing_instructions, _signature) = sign(signable_request, &signing_params)?.into_parts();
+
+    let mut request = reqwest::Client::new().post(&url).body(body);
+    for (header, value) in headers.into_iter().chain(signing_instructions.headers()) {
+        request = request.header(header, value);
+    }
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        anyhow::bail!("getting SSM parameter {name} failed: {}", response.text().await?);
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Output {
+        parameter: Parameter,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Parameter {
+        value: String,
     }
+    let output: Output = response.json().await?;
+    Ok(output.parameter.value)
 }
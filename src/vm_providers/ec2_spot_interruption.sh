@@ -0,0 +1,11 @@
+#!/bin/bash
+# Prints the spot interruption notice and exits once EC2 is about to reclaim the instance.
+# On-demand instances (e.g. after falling back) never get one.
+while true; do
+    TOKEN=$(curl -sf -X PUT http://169.254.169.254/latest/api/token -H 'X-aws-ec2-metadata-token-ttl-seconds: 300') || TOKEN=
+    if ACTION=$(curl -sf -H "X-aws-ec2-metadata-token: $TOKEN" http://169.254.169.254/latest/meta-data/spot/instance-action); then
+        echo "$ACTION"
+        exit 0
+    fi
+    sleep 5
+done
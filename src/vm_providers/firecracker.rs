@@ -0,0 +1,162 @@
+use super::{instance_name, run_dirs::RunDirs, SpawnedVm, VmProvider};
+use crate::{command_ext::CommandExt as _, steps};
+use anyhow::Context as _;
+use async_trait::async_trait;
+use clap::Args;
+use serde_json::json;
+use std::{fs, net::Ipv4Addr, path::PathBuf, process::Stdio};
+use tokio::{
+    process::Command,
+    time::{sleep, Duration},
+};
+
+/// Firecracker microVM (local)
+#[derive(Args, Clone)]
+#[command(
+    override_usage = color_print::cstr! {r#"<bold>fleeting</bold> <bold>firecracker</bold> [OPTIONS] --kernel <<PATH>> --rootfs <<PATH>> --tap <<IFNAME>> [COMMAND]...
+
+Boots a microVM from a kernel and a copy of a root filesystem image. The
+user_data is served from MMDS, and the root filesystem must run it at boot,
+e.g. from a systemd unit running:
+
+    ip route add 169.254.169.254 dev eth0
+    TOKEN=$(curl -sX PUT http://169.254.169.254/latest/api/token -H 'X-metadata-token-ttl-seconds: 60')
+    curl -sH "X-metadata-token: $TOKEN" http://169.254.169.254/user_data | bash
+
+<bold><underline>Requirements:</underline></bold>
+  - firecracker on PATH and access to /dev/kvm
+  - Root filesystem with systemd, sshd (root login with keys), curl and iptables
+  - A tap device with '--gateway-ip' configured on the host side, and NAT
+    for internet access. Only one VM at a time can use a tap device.
+
+"#},)]
+pub struct Firecracker {
+    /// Uncompressed guest kernel (vmlinux), must support 'ip=' configuration.
+    #[arg(long, value_name = "PATH")]
+    kernel: PathBuf,
+
+    /// ext4 root filesystem image. Each VM boots from its own copy.
+    #[arg(long, value_name = "PATH")]
+    rootfs: PathBuf,
+
+    /// Host tap device for the VM's network interface.
+    #[arg(long, value_name = "IFNAME")]
+    tap: String,
+
+    #[arg(long, default_value = "172.16.0.2")]
+    guest_ip: Ipv4Addr,
+
+    /// Host-side IP of the tap device.
+    #[arg(long, default_value = "172.16.0.1")]
+    gateway_ip: Ipv4Addr,
+
+    #[arg(long, default_value = "255.255.255.252")]
+    netmask: Ipv4Addr,
+
+    /// CPUs.
+    #[arg(long, default_value_t = 2)]
+    cpus: usize,
+
+    /// Memory, in GBs.
+    #[arg(long, default_value_t = 2)]
+    memory: usize,
+}
+
+#[async_trait]
+impl VmProvider for Firecracker {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
+        let step = steps::start();
+        log::info!("Checking firecracker installation...");
+        {
+            let version = Command::new("firecracker").arg("--version").capture_stdout().await?;
+            log::debug!("{}", String::from_utf8_lossy(&version).trim());
+        }
+        let run_dirs = RunDirs::new("firecracker", "firecracker")?;
+
+        let step = step.next();
+        log::info!("Purging old stopped fleeting VMs...");
+        {
+            let purged = run_dirs.purge_stopped()?;
+            log::info!("{purged} purged");
+        }
+
+        let step = step.next();
+        log::info!("Launching a microVM...");
+        let name = instance_name();
+        {
+            let run_dir = run_dirs.create(&name)?;
+
+            log::debug!("Copying rootfs...");
+            let rootfs = run_dir.join("rootfs.ext4");
+            // Without blocking the runtime on a multi-GB copy, and instantly on filesystems that can share extents
+            Command::new("cp")
+                .args(["--reflink=auto", "--sparse=always"])
+                .arg(&self.rootfs)
+                .arg(&rootfs)
+                .capture_stdout()
+                .await
+                .with_context(|| format!("copying {:?}", self.rootfs))?;
+
+            let config = json!({
+                "boot-source": {
+                    "kernel_image_path": self.kernel,
+                    // reboot=k: guest reboot (see `teardown_command`) terminates firecracker
+                    "boot_args": format!(
+                        "console=ttyS0 reboot=k panic=1 pci=off ip={}::{}:{}::eth0:off",
+                        self.guest_ip, self.gateway_ip, self.netmask
+                    ),
+                },
+                "drives": [{
+                    "drive_id": "rootfs",
+                    "path_on_host": rootfs,
+                    "is_root_device": true,
+                    "is_read_only": false,
+                }],
+                "machine-config": {
+                    "vcpu_count": self.cpus,
+                    "mem_size_mib": self.memory * 1024,
+                },
+                "network-interfaces": [{
+                    "iface_id": "eth0",
+                    "host_dev_name": self.tap,
+                }],
+                "mmds-config": {
+                    "version": "V2",
+                    "network_interfaces": ["eth0"],
+                },
+            });
+            fs::write(run_dir.join("config.json"), serde_json::to_string_pretty(&config)?)?;
+            fs::write(run_dir.join("metadata.json"), serde_json::to_string(&json!({ "user_data": user_data }))?)?;
+
+            let log_path = run_dir.join("firecracker.log");
+            let mut child = Command::new("firecracker")
+                .args(["--id", &name, "--no-api"])
+                .arg("--config-file")
+                .arg(run_dir.join("config.json"))
+                .arg("--metadata")
+                .arg(run_dir.join("metadata.json"))
+                .stdin(Stdio::null())
+                .stdout(fs::File::create(&log_path)?)
+                .stderr(fs::File::create(run_dir.join("firecracker.err"))?)
+                .detached()
+                .spawn()?;
+            fs::write(run_dir.join("pid"), child.id().expect("child pid").to_string())?;
+
+            // Invalid configuration makes firecracker exit right away
+            sleep(Duration::from_secs(1)).await;
+            if let Some(exit_status) = child.try_wait()? {
+                let stderr = fs::read_to_string(run_dir.join("firecracker.err")).unwrap_or_default();
+                anyhow::bail!("firecracker exited with {exit_status:?}: {} (see {log_path:?})", stderr.trim());
+            }
+        }
+        log::info!("{name}");
+
+        steps::end(step);
+        Ok(self.guest_ip.into())
+    }
+
+    fn teardown_command(&self) -> &str {
+        // Firecracker does not emulate power off, but exits when the guest reboots
+        "reboot"
+    }
+}
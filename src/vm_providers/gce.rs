@@ -1,27 +1,23 @@
-use super::VmProvider;
-use crate::{arch::Arch, steps};
+use super::{allowed_source_ranges, instance_name, DestroyGuard, SpawnedVm, VmProvider};
+use crate::{arch::Arch, steps, ubuntu_release::UbuntuRelease};
 use async_trait::async_trait;
 use clap::Args;
 use gcloud_sdk::google_rest_apis::compute_v1::{
+    configuration::Configuration,
     firewall::Direction,
-    firewalls_api::{ComputePeriodFirewallsPeriodGetParams, ComputePeriodFirewallsPeriodInsertParams},
+    firewalls_api::{ComputePeriodFirewallsPeriodDeleteParams, ComputePeriodFirewallsPeriodInsertParams, ComputePeriodFirewallsPeriodListParams},
     instance::Status,
     instances_api::{
-        ComputePeriodInstancesPeriodDeleteParams, ComputePeriodInstancesPeriodGetParams, ComputePeriodInstancesPeriodInsertParams,
-        ComputePeriodInstancesPeriodListParams,
+        ComputePeriodInstancesPeriodAggregatedListParams, ComputePeriodInstancesPeriodDeleteParams, ComputePeriodInstancesPeriodGetParams,
+        ComputePeriodInstancesPeriodInsertParams, ComputePeriodInstancesPeriodListParams,
     },
     machine_types_api::ComputePeriodMachineTypesPeriodListParams,
-    AccessConfig, AttachedDisk, AttachedDiskInitializeParams, Error, Firewall, FirewallAllowedInner, Instance, Metadata, MetadataItemsInner, NetworkInterface,
-    Scheduling, Tags,
+    AccessConfig, AttachedDisk, AttachedDiskInitializeParams, CustomerEncryptionKey, Error, Firewall, FirewallAllowedInner, Instance, Metadata,
+    MetadataItemsInner, NetworkInterface, Scheduling, Tags,
 };
-use rand::distributions::Alphanumeric;
-use rand::Rng;
-use std::{net::Ipv4Addr, str::FromStr as _};
+use std::{collections::HashSet, net::Ipv4Addr, str::FromStr as _};
 use tokio::time::{sleep, Duration};
 
-const INSTANCE_TAG: &str = "fleeting";
-const INBOUND_FIREWALL_RULE_NAME: &str = "fleeting-allow-inbound";
-
 /// Google Compute Engine
 #[derive(Args, Clone)]
 #[command(
@@ -39,9 +35,10 @@ const INBOUND_FIREWALL_RULE_NAME: &str = "fleeting-allow-inbound";
 
 <bold><underline>Limitations:</underline></bold>
 While GCE instances will automatically stop, they will not be automatically
-deleted. fleeting collects garbage at the beginning of the run, but you will
-be left with a small number of stopped instances and will continue to pay for
-their associated disks. Hopefully, this will be resolved in the future with
+deleted. fleeting deletes them at the end of the run and collects garbage at
+the beginning of the run, but if it is interrupted, you may be left with a
+small number of stopped instances and will continue to pay for their
+associated disks. Hopefully, this will be resolved in the future with
 termination_time / max_run_duration, once GCE client libraries support it.
 
 "#},)]
@@ -56,20 +53,49 @@ pub struct Gce {
     #[arg(long, default_value = "e2-micro")]
     machine_type: String,
 
+    /// Image or image family, e.g. projects/my-project/global/images/family/golden-ubuntu [default: Canonical's Ubuntu for the machine type]
+    #[arg(long, conflicts_with = "ubuntu_release")]
+    image: Option<String>,
+
+    /// Release of Canonical's Ubuntu image.
+    #[arg(long, default_value = "24.04")]
+    ubuntu_release: UbuntuRelease,
+
     /// Disk size, in GiBs.
     #[arg(long)]
     disk: Option<usize>,
+
+    /// Disk type, e.g. pd-ssd, or hyperdisk-balanced on machine types that support it.
+    #[arg(long, default_value = "pd-balanced")]
+    disk_type: String,
+
+    /// Provisioned IOPS, for hyperdisk and pd-extreme disks.
+    #[arg(long)]
+    disk_iops: Option<u64>,
+
+    /// Provisioned throughput, in MiB/s, for hyperdisk disks.
+    #[arg(long)]
+    disk_throughput: Option<u64>,
+
+    /// Cloud KMS key to encrypt the disk with (projects/.../locations/.../keyRings/.../cryptoKeys/...).
+    /// The Compute Engine service agent needs permission to use it.
+    #[arg(long)]
+    disk_kms_key: Option<String>,
+
+    /// Comma-separated CIDRs allowed to reach ssh and dockerd [default: this host's public IP]
+    #[arg(long = "allow-cidr", value_name = "CIDR", value_delimiter = ',')]
+    allow_cidrs: Vec<String>,
 }
 
 #[async_trait]
 impl VmProvider for Gce {
-    async fn spawn(&self, user_data: &str) -> anyhow::Result<Ipv4Addr> {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
         let step = steps::start();
         log::info!("Loading Google Cloud configuration...");
         let google_rest_api = gcloud_sdk::GoogleRestApi::new().await?;
         let configuration = google_rest_api.create_google_compute_v1_config().await?;
 
-        let step: _ = step.next();
+        let step = step.next();
         log::info!("Delete terminated fleeting instances...");
         {
             let instances = gcloud_sdk::google_rest_apis::compute_v1::instances_api::compute_instances_list(
@@ -102,9 +128,63 @@ impl VmProvider for Gce {
             log::info!("{} deleted", instances.len());
         }
 
-        let step: _ = step.next();
+        let step = step.next();
+        log::info!("Deleting unused fleeting firewall rules...");
+        {
+            // Firewall rules are global, so instances of every zone count
+            let instance_names = gcloud_sdk::google_rest_apis::compute_v1::instances_api::compute_instances_aggregated_list(
+                &configuration,
+                ComputePeriodInstancesPeriodAggregatedListParams {
+                    project: self.project.to_owned(),
+                    filter: Some(r#"name = "fleeting-*""#.to_owned()),
+                    ..Default::default()
+                },
+            )
+            .await?
+            .items
+            .unwrap_or_default()
+            .into_values()
+            .flat_map(|scoped_list| scoped_list.instances.unwrap_or_default())
+            .filter_map(|instance| instance.name)
+            .collect::<HashSet<_>>();
+
+            // Also matches the world-open 'fleeting-allow-inbound' rule that earlier versions shared between instances
+            let rules = gcloud_sdk::google_rest_apis::compute_v1::firewalls_api::compute_firewalls_list(
+                &configuration,
+                ComputePeriodFirewallsPeriodListParams {
+                    project: self.project.to_owned(),
+                    filter: Some(r#"name = "fleeting-*""#.to_owned()),
+                    ..Default::default()
+                },
+            )
+            .await?
+            .items
+            .unwrap_or_default();
+
+            let mut deleted = 0;
+            for rule in rules {
+                let rule_name = rule.name.unwrap();
+                assert!(rule_name.starts_with("fleeting-"));
+                // Runs create their rule (named after the instance) before launching the instance
+                let recent = rule
+                    .creation_timestamp
+                    .and_then(|timestamp| chrono::DateTime::parse_from_rfc3339(&timestamp).ok())
+                    .is_some_and(|created| chrono::Utc::now().signed_duration_since(created) < chrono::TimeDelta::minutes(10));
+                if instance_names.contains(&rule_name) || recent {
+                    continue;
+                }
+                delete_firewall_rule(&configuration, &self.project, &rule_name).await?;
+                deleted += 1;
+            }
+            log::info!("{deleted} deleted");
+        }
+
+        let step = step.next();
         log::info!("Looking up machine type...");
-        let source_image = {
+        let source_image = if let Some(image) = &self.image {
+            log::info!("Not needed, using {image}");
+            image.clone()
+        } else {
             // Problem 1: The client lib does not support the architecture field, but we can squeeze a string into a filter and see what matches
             let mut matched_archs = vec![];
             for google_arch in ["arm64", "x86_64"] {
@@ -135,66 +215,40 @@ impl VmProvider for Gce {
                 x => panic!("multiple architecture filters matched: {x:?}"),
             };
 
-            format!("projects/ubuntu-os-cloud/global/images/family/ubuntu-2404-lts-{}", arch.as_dpkg())
+            format!("projects/ubuntu-os-cloud/global/images/family/{}", image_family(self.ubuntu_release, arch))
         };
 
-        let step: _ = step.next();
-        log::info!("Creating firewall rule if needed...");
+        let step = step.next();
+        log::info!("Creating firewall rule...");
+        let instance_name = instance_name();
         {
-            let result = gcloud_sdk::google_rest_apis::compute_v1::firewalls_api::compute_firewalls_get(
+            let source_ranges = allowed_source_ranges(&self.allow_cidrs).await?;
+            gcloud_sdk::google_rest_apis::compute_v1::firewalls_api::compute_firewalls_insert(
                 &configuration,
-                ComputePeriodFirewallsPeriodGetParams {
+                ComputePeriodFirewallsPeriodInsertParams {
                     project: self.project.to_owned(),
-                    firewall: INBOUND_FIREWALL_RULE_NAME.to_owned(),
+                    firewall: Some(Firewall {
+                        name: Some(instance_name.clone()),
+                        description: Some("fleeting ephemeral instance".to_owned()),
+                        // The instance is tagged with its name
+                        target_tags: Some(vec![instance_name.clone()]),
+                        source_ranges: Some(source_ranges.clone()),
+                        direction: Some(Direction::Ingress),
+                        allowed: Some(vec![FirewallAllowedInner {
+                            ip_protocol: Some("tcp".to_owned()),
+                            ports: Some(vec!["22".to_owned(), "2376".to_owned()]),
+                        }]),
+                        ..Default::default()
+                    }),
                     ..Default::default()
                 },
             )
-            .await;
-
-            match result {
-                Err(Error::ResponseError(content)) if content.status.as_u16() == 404 => {
-                    gcloud_sdk::google_rest_apis::compute_v1::firewalls_api::compute_firewalls_insert(
-                        &configuration,
-                        ComputePeriodFirewallsPeriodInsertParams {
-                            project: self.project.to_owned(),
-                            firewall: Some(Firewall {
-                                name: Some(INBOUND_FIREWALL_RULE_NAME.to_owned()),
-                                target_tags: Some(vec![INSTANCE_TAG.to_owned()]),
-                                direction: Some(Direction::Ingress),
-                                allowed: Some(vec![
-                                    FirewallAllowedInner { ip_protocol: Some("tcp".to_owned()), ..Default::default() },
-                                    FirewallAllowedInner { ip_protocol: Some("udp".to_owned()), ..Default::default() },
-                                    FirewallAllowedInner { ip_protocol: Some("icmp".to_owned()), ..Default::default() },
-                                ]),
-                                ..Default::default()
-                            }),
-                            ..Default::default()
-                        },
-                    )
-                    .await?;
-                    log::info!("{INBOUND_FIREWALL_RULE_NAME} (created)");
-                }
-                Err(e) => return Err(e.into()),
-                Ok(rule) => {
-                    assert_eq!(rule.name.unwrap(), INBOUND_FIREWALL_RULE_NAME);
-                    log::info!("{INBOUND_FIREWALL_RULE_NAME} (already existed)");
-                }
-            };
+            .await?;
+            log::info!("{instance_name} (allowing {})", source_ranges.join(", "));
         };
 
-        let step: _ = step.next();
+        let step = step.next();
         log::info!("Launching an instance...");
-        let instance_name = format!(
-            "fleeting-{}-{}",
-            std::process::id(),
-            // for dedup across hosts running fleeting:
-            rand::thread_rng()
-                .sample_iter(&Alphanumeric)
-                .take(8)
-                .map(char::from)
-                .collect::<String>()
-                .to_lowercase()
-        );
         {
             let result = gcloud_sdk::google_rest_apis::compute_v1::instances_api::compute_instances_insert(
                 &configuration,
@@ -210,13 +264,19 @@ impl VmProvider for Gce {
                             auto_delete: Some(true),
                             initialize_params: Some(Box::new(AttachedDiskInitializeParams {
                                 disk_size_gb: self.disk.map(|n| n.to_string()),
-                                disk_type: Some(format!("zones/{}/diskTypes/pd-balanced", self.zone)), // SSD
+                                disk_type: Some(format!("zones/{}/diskTypes/{}", self.zone, self.disk_type)),
+                                provisioned_iops: self.disk_iops.map(|n| n.to_string()),
+                                provisioned_throughput: self.disk_throughput.map(|n| n.to_string()),
                                 source_image: Some(source_image),
                                 ..Default::default()
                             })),
+                            disk_encryption_key: self
+                                .disk_kms_key
+                                .as_ref()
+                                .map(|kms_key_name| Box::new(CustomerEncryptionKey { kms_key_name: Some(kms_key_name.clone()), ..Default::default() })),
                             ..Default::default()
                         }]),
-                        tags: Some(Box::new(Tags { items: Some(vec![INSTANCE_TAG.to_owned()]), ..Default::default() })),
+                        tags: Some(Box::new(Tags { items: Some(vec![instance_name.clone()]), ..Default::default() })),
                         network_interfaces: Some(vec![NetworkInterface {
                             access_configs: Some(vec![AccessConfig { ..Default::default() }]),
                             ..Default::default()
@@ -249,14 +309,33 @@ impl VmProvider for Gce {
             .await;
 
             if let Err(e) = result {
+                log::info!("Deleting firewall rule after failed launch...");
+                if let Err(e) = delete_firewall_rule(&configuration, &self.project, &instance_name).await {
+                    log::error!("Failed to delete firewall rule: {e:#}");
+                }
                 // Explicitly use Debug selector, because Display (which we normally use) is useless in this SDK
                 anyhow::bail!("failed to launch instance: {e:#?}");
             }
         };
 
-        let step: _ = step.next();
+        let destroy = {
+            let configuration = configuration.clone();
+            let project = self.project.clone();
+            let zone = self.zone.clone();
+            let instance_name = instance_name.clone();
+            DestroyGuard::new(async move {
+                gcloud_sdk::google_rest_apis::compute_v1::instances_api::compute_instances_delete(
+                    &configuration,
+                    ComputePeriodInstancesPeriodDeleteParams { project: project.clone(), zone, instance: instance_name.clone(), ..Default::default() },
+                )
+                .await?;
+                delete_firewall_rule(&configuration, &project, &instance_name).await
+            })
+        };
+
+        let step = step.next();
         log::info!("Waiting for instance to start...");
-        let public_ip = {
+        let started = async {
             let instance = loop {
                 log::debug!("Retrieving instance status...");
                 let instance = gcloud_sdk::google_rest_apis::compute_v1::instances_api::compute_instances_get(
@@ -278,7 +357,7 @@ impl VmProvider for Gce {
                 }
             };
 
-            instance
+            let public_ip: Ipv4Addr = instance
                 .network_interfaces
                 .expect_one("network interface")
                 .access_configs
@@ -286,11 +365,49 @@ impl VmProvider for Gce {
                 .nat_ip
                 .expect("nat ip")
                 .parse()
-                .expect("parsable ip")
-        };
+                .expect("parsable ip");
+            Ok(public_ip)
+        }
+        .await;
 
         steps::end(step);
-        Ok(public_ip)
+        match started {
+            Ok(public_ip) => Ok(SpawnedVm { destroy: Some(destroy.disarm()), ..public_ip.into() }),
+            Err(e) => {
+                log::info!("Deleting instance after failed start...");
+                if let Err(e) = destroy.disarm().await {
+                    log::error!("Failed to delete instance: {e:#}");
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Canonical's image family, e.g. ubuntu-2404-lts-amd64, ubuntu-2410-arm64, but ubuntu-2204-lts for amd64.
+fn image_family(release: UbuntuRelease, arch: Arch) -> String {
+    let lts = if release.is_lts() { "-lts" } else { "" };
+    let arch_suffix = match arch {
+        Arch::Amd64 if release < UbuntuRelease::new(23, 4) => String::new(),
+        arch => format!("-{}", arch.as_dpkg()),
+    };
+    format!("ubuntu-{}{lts}{arch_suffix}", release.as_compact())
+}
+
+/// Succeeds if the rule is already gone, e.g. deleted by a concurrent run's garbage collection.
+async fn delete_firewall_rule(configuration: &Configuration, project: &str, name: &str) -> anyhow::Result<()> {
+    let result = gcloud_sdk::google_rest_apis::compute_v1::firewalls_api::compute_firewalls_delete(
+        configuration,
+        ComputePeriodFirewallsPeriodDeleteParams { project: project.to_owned(), firewall: name.to_owned(), ..Default::default() },
+    )
+    .await;
+    match result {
+        Ok(_) => Ok(()),
+        Err(Error::ResponseError(response)) if response.status.as_u16() == 404 => {
+            log::debug!("{name} already deleted");
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
     }
 }
 
@@ -305,3 +422,17 @@ impl<T> OptionVecExt<T> for Option<Vec<T>> {
         vec.into_iter().nth(0).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_families() {
+        let family = |release: &str, arch| image_family(release.parse().unwrap(), arch);
+        assert_eq!(family("22.04", Arch::Amd64), "ubuntu-2204-lts");
+        assert_eq!(family("22.04", Arch::Arm64), "ubuntu-2204-lts-arm64");
+        assert_eq!(family("24.04", Arch::Amd64), "ubuntu-2404-lts-amd64");
+        assert_eq!(family("24.10", Arch::Arm64), "ubuntu-2410-arm64");
+    }
+}
@@ -0,0 +1,245 @@
+use super::{bearer_client::BearerClient, instance_name, SpawnedVm, VmProvider};
+use crate::{steps, ubuntu_release::UbuntuRelease};
+use anyhow::Context as _;
+use async_trait::async_trait;
+use clap::Args;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::json;
+use std::{env, fmt, net::Ipv4Addr};
+use tokio::time::{sleep, Duration};
+
+const API_URL: &str = "https://api.hetzner.cloud/v1";
+const LABEL: &str = "fleeting";
+const FIREWALL_NAME: &str = "fleeting";
+
+/// Hetzner Cloud
+#[derive(Args, Clone)]
+#[command(
+    override_usage = color_print::cstr! {r#"<bold>fleeting</bold> <bold>hetzner</bold> [OPTIONS] [COMMAND]...
+
+<bold><underline>Authentication:</underline></bold>
+  - Environment variable HCLOUD_TOKEN (API token with read & write permissions)
+
+<bold><underline>Limitations:</underline></bold>
+Hetzner Cloud servers stop, but are not deleted, when shut down. fleeting
+collects garbage at the beginning of the run, but you will be left with a
+small number of stopped servers and will continue to pay for them.
+
+"#},)]
+pub struct Hetzner {
+    #[arg(long, default_value = "fsn1")]
+    location: String,
+
+    /// Server type, e.g. 'cax11' (arm64) or 'cx22' (amd64).
+    #[arg(long, default_value = "cax11")]
+    server_type: String,
+
+    /// Release of Canonical's Ubuntu image.
+    #[arg(long, default_value = "24.04")]
+    ubuntu_release: UbuntuRelease,
+}
+
+#[async_trait]
+impl VmProvider for Hetzner {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
+        let step = steps::start();
+        log::info!("Loading Hetzner Cloud configuration...");
+        let client = {
+            let token = env::var("HCLOUD_TOKEN").context("HCLOUD_TOKEN not set")?;
+            Client::new(API_URL, token)
+        };
+
+        let step = step.next();
+        log::info!("Delete stopped fleeting servers...");
+        {
+            #[derive(Deserialize)]
+            struct Servers {
+                servers: Vec<Server>,
+            }
+            let servers: Servers = client
+                .request(Method::GET, &format!("/servers?label_selector={LABEL}&status=off"), None)
+                .await?;
+
+            // The label alone could also match servers the user labeled themselves
+            let stopped = servers.servers.iter().filter(|server| server.name.starts_with("fleeting-")).collect::<Vec<_>>();
+            for server in &stopped {
+                client
+                    .request::<serde_json::Value>(Method::DELETE, &format!("/servers/{}", server.id), None)
+                    .await?;
+            }
+            log::info!("{} deleted", stopped.len());
+        }
+
+        let step = step.next();
+        log::info!("Looking up server type...");
+        let image_id = {
+            #[derive(Deserialize)]
+            struct ServerTypes {
+                server_types: Vec<ServerType>,
+            }
+            #[derive(Deserialize)]
+            struct ServerType {
+                architecture: String,
+            }
+            let server_types: ServerTypes = client.request(Method::GET, &format!("/server_types?name={}", self.server_type), None).await?;
+            let [server_type] = &*server_types.server_types else {
+                anyhow::bail!("unknown server type: {}", self.server_type)
+            };
+
+            #[derive(Deserialize)]
+            struct Images {
+                images: Vec<Image>,
+            }
+            #[derive(Deserialize)]
+            struct Image {
+                id: u64,
+            }
+            let images: Images = client
+                .request(
+                    Method::GET,
+                    &format!(
+                        "/images?type=system&name=ubuntu-{}&architecture={}",
+                        self.ubuntu_release, server_type.architecture
+                    ),
+                    None,
+                )
+                .await?;
+            let [image] = &*images.images else {
+                anyhow::bail!("no ubuntu-{} image for architecture: {}", self.ubuntu_release, server_type.architecture)
+            };
+            image.id
+        };
+
+        let step = step.next();
+        log::info!("Creating firewall if needed...");
+        let firewall_id = {
+            #[derive(Deserialize)]
+            struct Firewalls {
+                firewalls: Vec<Firewall>,
+            }
+            #[derive(Deserialize)]
+            struct CreatedFirewall {
+                firewall: Firewall,
+            }
+            #[derive(Deserialize)]
+            struct Firewall {
+                id: u64,
+            }
+            let firewalls: Firewalls = client.request(Method::GET, &format!("/firewalls?name={FIREWALL_NAME}"), None).await?;
+            match &*firewalls.firewalls {
+                [] => {
+                    let everywhere = json!(["0.0.0.0/0", "::/0"]);
+                    let created: CreatedFirewall = client
+                        .request(
+                            Method::POST,
+                            "/firewalls",
+                            Some(json!({
+                                "name": FIREWALL_NAME,
+                                "labels": { LABEL: "true" },
+                                "rules": [
+                                    { "direction": "in", "protocol": "tcp", "port": "1-65535", "source_ips": everywhere },
+                                    { "direction": "in", "protocol": "udp", "port": "1-65535", "source_ips": everywhere },
+                                    { "direction": "in", "protocol": "icmp", "source_ips": everywhere },
+                                ],
+                            })),
+                        )
+                        .await?;
+                    log::info!("{} (created)", created.firewall.id);
+                    created.firewall.id
+                }
+                [firewall] => {
+                    log::info!("{} (already existed)", firewall.id);
+                    firewall.id
+                }
+                x => anyhow::bail!("{} matching firewalls", x.len()),
+            }
+        };
+
+        let step = step.next();
+        log::info!("Launching a server...");
+        let server_id = {
+            #[derive(Deserialize)]
+            struct CreatedServer {
+                server: Server,
+            }
+            let created: CreatedServer = client
+                .request(
+                    Method::POST,
+                    "/servers",
+                    Some(json!({
+                        "name": instance_name(),
+                        "server_type": self.server_type,
+                        "image": image_id,
+                        "location": self.location,
+                        "user_data": user_data,
+                        "labels": { LABEL: "true" },
+                        "firewalls": [{ "firewall": firewall_id }],
+                        "start_after_create": true,
+                    })),
+                )
+                .await?;
+            created.server.id
+        };
+        log::info!("{server_id}");
+
+        let step = step.next();
+        log::info!("Waiting for server to start...");
+        let public_ip = {
+            #[derive(Deserialize)]
+            struct GetServer {
+                server: Server,
+            }
+            let server = loop {
+                log::debug!("Retrieving server status...");
+                let GetServer { server } = client.request(Method::GET, &format!("/servers/{server_id}"), None).await?;
+                match server.status.as_str() {
+                    "initializing" | "starting" => sleep(Duration::from_secs(1)).await,
+                    "running" => break server,
+                    state => anyhow::bail!("server transitioned into state: {state}"),
+                }
+            };
+            server.public_net.ipv4.context("server has no public IPv4 address")?.ip
+        };
+
+        steps::end(step);
+        Ok(public_ip.into())
+    }
+}
+
+#[derive(Deserialize)]
+struct Server {
+    id: u64,
+    name: String,
+    status: String,
+    public_net: PublicNet,
+}
+
+#[derive(Deserialize)]
+struct PublicNet {
+    ipv4: Option<PublicIpv4>,
+}
+
+#[derive(Deserialize)]
+struct PublicIpv4 {
+    ip: Ipv4Addr,
+}
+
+type Client = BearerClient<ErrorResponse>;
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ApiError,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    code: String,
+    message: String,
+}
+
+impl fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.error.message, self.error.code)
+    }
+}
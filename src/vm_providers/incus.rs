@@ -0,0 +1,254 @@
+use super::{instance_name, SpawnedVm, VmProvider};
+use crate::{steps, ubuntu_release::UbuntuRelease, unix_http};
+use async_trait::async_trait;
+use clap::Args;
+use hyper::Method;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+use tokio::time::sleep;
+
+const SOCKET_PATHS: &[&str] = &["/var/lib/incus/unix.socket", "/var/snap/lxd/common/lxd/unix.socket", "/var/lib/lxd/unix.socket"];
+
+/// Incus/LXD system container or VM (local)
+#[derive(Args, Clone)]
+#[command(
+    override_usage = color_print::cstr! {r#"<bold>fleeting</bold> <bold>incus</bold> [OPTIONS] [COMMAND]...
+
+Launches an Ubuntu system container (with nesting enabled) or VM via
+the local Incus or LXD daemon. The current user must have access to its Unix
+socket, e.g. by being a member of the 'incus-admin' or 'lxd' group.
+
+"#},)]
+pub struct Incus {
+    /// Launch a virtual machine instead of a system container.
+    #[arg(long)]
+    vm: bool,
+
+    /// [default: first existing of /var/lib/incus/unix.socket, /var/snap/lxd/common/lxd/unix.socket, /var/lib/lxd/unix.socket]
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    /// CPUs.
+    #[arg(long)]
+    cpus: Option<usize>,
+
+    /// Memory, in GBs.
+    #[arg(long)]
+    memory: Option<usize>,
+
+    /// Disk size, in GiBs.
+    #[arg(long)]
+    disk: Option<usize>,
+
+    /// Release of Canonical's Ubuntu image.
+    #[arg(long, default_value = "24.04")]
+    ubuntu_release: UbuntuRelease,
+}
+
+#[async_trait]
+impl VmProvider for Incus {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
+        let step = steps::start();
+        log::info!("Connecting to daemon...");
+        let (client, server) = {
+            let socket_path = match &self.socket {
+                Some(path) => path.clone(),
+                None => SOCKET_PATHS
+                    .iter()
+                    .map(PathBuf::from)
+                    .find(|path| path.exists())
+                    .ok_or(anyhow::format_err!("no Incus/LXD socket found in: {SOCKET_PATHS:?}"))?,
+            };
+            let client = Client { socket_path };
+
+            #[derive(Deserialize, Debug)]
+            struct Server {
+                environment: Environment,
+            }
+            #[derive(Deserialize, Debug)]
+            struct Environment {
+                server: String,
+                server_version: String,
+            }
+            let server: Server = client.request(Method::GET, "/1.0", None).await?;
+            log::info!("{} {}", server.environment.server, server.environment.server_version);
+            (client, server.environment.server)
+        };
+
+        let step = step.next();
+        log::info!("Purging old stopped fleeting instances...");
+        {
+            #[derive(Deserialize, Debug)]
+            struct Instance {
+                name: String,
+                status: String,
+            }
+            let instances: Vec<Instance> = client.request(Method::GET, "/1.0/instances?recursion=1", None).await?;
+            log::debug!("listed instances: {instances:?}");
+            let orphans = instances
+                .into_iter()
+                .filter(|instance| instance.name.starts_with("fleeting-") && instance.status == "Stopped")
+                .collect::<Vec<_>>();
+            for instance in &orphans {
+                client
+                    .request_and_wait(Method::DELETE, &format!("/1.0/instances/{}", instance.name), None)
+                    .await?;
+            }
+            log::info!("{} purged", orphans.len());
+        }
+
+        let step = step.next();
+        log::info!("Launching an instance...");
+        let name = instance_name();
+        {
+            // Canonical's image server is not available to Incus, and the community one not to LXD.
+            let source = match server.as_str() {
+                "lxd" => json!({
+                    "type": "image",
+                    "mode": "pull",
+                    "protocol": "simplestreams",
+                    "server": "https://cloud-images.ubuntu.com/releases",
+                    "alias": self.ubuntu_release.to_string(),
+                }),
+                _ => json!({
+                    "type": "image",
+                    "mode": "pull",
+                    "protocol": "simplestreams",
+                    "server": "https://images.linuxcontainers.org",
+                    "alias": format!("ubuntu/{}/cloud", self.ubuntu_release),
+                }),
+            };
+
+            let mut config = HashMap::from([("cloud-init.user-data".to_owned(), user_data.to_owned())]);
+            if !self.vm {
+                // Required for running dockerd inside
+                config.insert("security.nesting".to_owned(), "true".to_owned());
+                config.insert("security.syscalls.intercept.mknod".to_owned(), "true".to_owned());
+                config.insert("security.syscalls.intercept.setxattr".to_owned(), "true".to_owned());
+            }
+            if let Some(cpus) = self.cpus {
+                config.insert("limits.cpu".to_owned(), cpus.to_string());
+            }
+            if let Some(memory) = self.memory {
+                config.insert("limits.memory".to_owned(), format!("{memory}GiB"));
+            }
+
+            let mut devices = json!({});
+            if let Some(disk) = self.disk {
+                devices["root"] = json!({ "type": "disk", "path": "/", "pool": "default", "size": format!("{disk}GiB") });
+            }
+
+            client
+                .request_and_wait(
+                    Method::POST,
+                    "/1.0/instances",
+                    Some(json!({
+                        "name": name,
+                        "type": if self.vm { "virtual-machine" } else { "container" },
+                        "source": source,
+                        "config": config,
+                        "devices": devices,
+                        "start": true,
+                    })),
+                )
+                .await?;
+        }
+        log::info!("{name}");
+
+        let step = step.next();
+        log::info!("Getting instance IP...");
+        let ip = {
+            #[derive(Deserialize)]
+            struct State {
+                network: Option<HashMap<String, Network>>,
+            }
+            #[derive(Deserialize)]
+            struct Network {
+                addresses: Vec<Address>,
+            }
+            #[derive(Deserialize)]
+            struct Address {
+                family: String,
+                address: String,
+                scope: String,
+            }
+
+            // VMs need to boot and start the agent first
+            let deadline = SystemTime::now() + Duration::from_secs(120);
+            loop {
+                if SystemTime::now() > deadline {
+                    anyhow::bail!("instance did not get an IPv4 address in time limit");
+                }
+                let state: State = client.request(Method::GET, &format!("/1.0/instances/{name}/state"), None).await?;
+                let ip = state
+                    .network
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|(interface, _)| interface != "lo")
+                    .flat_map(|(_, network)| network.addresses)
+                    .find(|address| address.family == "inet" && address.scope == "global");
+                match ip {
+                    Some(address) => break address.address.parse::<Ipv4Addr>()?,
+                    None => sleep(Duration::from_secs(1)).await,
+                }
+            }
+        };
+
+        steps::end(step);
+        Ok(ip.into())
+    }
+}
+
+struct Client {
+    socket_path: PathBuf,
+}
+
+impl Client {
+    /// Returns the metadata of a synchronous response.
+    async fn request<T: DeserializeOwned>(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> anyhow::Result<T> {
+        let response = self.send(method, path, body).await?;
+        Ok(serde_json::from_value(response.metadata)?)
+    }
+
+    /// Sends a request that starts a background operation, and waits for it to complete.
+    async fn request_and_wait(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> anyhow::Result<()> {
+        let response = self.send(method, path, body).await?;
+        let operation = response.operation.ok_or(anyhow::format_err!("expected an operation"))?;
+
+        #[derive(Deserialize)]
+        struct Operation {
+            status: String,
+            err: String,
+        }
+        let operation: Operation = self.request(Method::GET, &format!("{operation}/wait"), None).await?;
+        match operation.status.as_str() {
+            "Success" => Ok(()),
+            status => anyhow::bail!("{path}: operation {status}: {}", operation.err),
+        }
+    }
+
+    async fn send(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> anyhow::Result<Response> {
+        let (_status, body) = unix_http::request(&self.socket_path, method.clone(), path, body.as_ref()).await?;
+        let response: Response = serde_json::from_slice(&body)?;
+        if response.r#type == "error" {
+            anyhow::bail!("{method} {path} failed: {}", response.error);
+        }
+        Ok(response)
+    }
+}
+
+#[derive(Deserialize)]
+struct Response {
+    r#type: String,
+    #[serde(default)]
+    error: String,
+    #[serde(default)]
+    metadata: serde_json::Value,
+    operation: Option<String>,
+}
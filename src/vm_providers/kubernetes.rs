@@ -0,0 +1,379 @@
+use super::{instance_name, DestroyGuard, SpawnedVm, VmAddress, VmProvider};
+use crate::steps;
+use async_trait::async_trait;
+use clap::{Args, ValueEnum};
+use futures::FutureExt as _;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr},
+    process::Stdio,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
+    net::lookup_host,
+    process::Command,
+    time::sleep,
+};
+
+const MANAGED_BY_LABEL: &str = "app.kubernetes.io/managed-by=fleeting";
+const INSTANCE_LABEL: &str = "fleeting-instance";
+
+/// Kubernetes pod
+#[derive(Args, Clone)]
+#[command(
+    override_usage = color_print::cstr! {r#"<bold>fleeting</bold> <bold>kubernetes</bold> [OPTIONS] [COMMAND]...
+
+Runs a privileged pod standing in for a VM: its main process installs and
+starts sshd (unless the image already has it) and then runs the user_data.
+/var/lib/docker is an emptyDir volume.
+
+<bold><underline>Authentication:</underline></bold>
+  - kubectl's configuration (KUBECONFIG or ~/.kube/config), see '--context'
+
+<bold><underline>Requirements:</underline></bold>
+  - kubectl on PATH
+  - Privileged pods allowed in the namespace
+  - For '--expose load-balancer', a load balancer implementation
+
+<bold><underline>Limitations:</underline></bold>
+Pods finish, but are not deleted, when fleeting is killed before the run
+ends. fleeting deletes finished pods at the beginning of the run.
+
+"#},)]
+pub struct Kubernetes {
+    /// kubeconfig context [default: current context]
+    #[arg(long)]
+    context: Option<String>,
+
+    /// [default: context's namespace]
+    #[arg(long)]
+    namespace: Option<String>,
+
+    /// Ubuntu-based image. Pre-installing openssh-server, curl and iptables speeds up startup.
+    #[arg(long, default_value = "ubuntu:24.04")]
+    image: String,
+
+    /// How to reach sshd and dockerd in the pod.
+    #[arg(long, value_enum, default_value_t = Expose::PortForward)]
+    expose: Expose,
+
+    /// CPUs, requested and limit.
+    #[arg(long)]
+    cpus: Option<usize>,
+
+    /// Memory, in GBs, requested and limit.
+    #[arg(long)]
+    memory: Option<usize>,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum Expose {
+    /// `kubectl port-forward` to local ports
+    PortForward,
+    /// Service of type LoadBalancer
+    LoadBalancer,
+}
+
+#[async_trait]
+impl VmProvider for Kubernetes {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
+        let step = steps::start();
+        log::info!("Checking kubectl installation...");
+        {
+            #[derive(Deserialize)]
+            #[serde(rename_all = "camelCase")]
+            struct Version {
+                client_version: ClientVersion,
+            }
+            #[derive(Deserialize)]
+            #[serde(rename_all = "camelCase")]
+            struct ClientVersion {
+                git_version: String,
+            }
+            let version: Version = self.kubectl_json(&["version", "--client", "-o", "json"]).await?;
+            log::debug!("kubectl {}", version.client_version.git_version);
+        }
+
+        let step = step.next();
+        log::info!("Deleting finished fleeting pods...");
+        {
+            let pods: List<Pod> = self.kubectl_json(&["get", "pods", "-l", MANAGED_BY_LABEL, "-o", "json"]).await?;
+            let (finished, others): (Vec<_>, Vec<_>) = pods
+                .items
+                .into_iter()
+                .partition(|pod| pod.status.phase == "Succeeded" || pod.status.phase == "Failed");
+            for pod in &finished {
+                self.kubectl(&["delete", "pod", &pod.metadata.name, "--wait=false"], None).await?;
+            }
+            log::info!("{} deleted", finished.len());
+
+            log::debug!("Deleting orphaned services...");
+            let existing = others.into_iter().map(|pod| pod.metadata.name).collect::<HashSet<_>>();
+            let services: List<Service> = self.kubectl_json(&["get", "services", "-l", MANAGED_BY_LABEL, "-o", "json"]).await?;
+            for service in services.items {
+                if !existing.contains(&service.metadata.name) {
+                    self.kubectl(&["delete", "service", &service.metadata.name, "--wait=false"], None).await?;
+                }
+            }
+        }
+
+        let step = step.next();
+        log::info!("Creating a pod...");
+        let name = instance_name();
+        {
+            let labels = json!({ "app.kubernetes.io/managed-by": "fleeting", INSTANCE_LABEL: name });
+            let mut resources = json!({});
+            if let Some(cpus) = self.cpus {
+                resources["cpu"] = json!(cpus);
+            }
+            if let Some(memory) = self.memory {
+                resources["memory"] = json!(format!("{memory}Gi"));
+            }
+            let pod = json!({
+                "apiVersion": "v1",
+                "kind": "Pod",
+                "metadata": { "name": name, "labels": labels },
+                "spec": {
+                    "restartPolicy": "Never",
+                    "terminationGracePeriodSeconds": 5,
+                    "containers": [{
+                        "name": "fleeting",
+                        "image": self.image,
+                        "command": ["bash", "-c", include_str!("container_bootstrap.sh"), "bootstrap", user_data],
+                        "securityContext": { "privileged": true },
+                        "ports": [
+                            { "name": "ssh", "containerPort": 22 },
+                            { "name": "docker", "containerPort": 2376 },
+                        ],
+                        // Ready once the bootstrap started sshd, so port-forward doesn't hand out a port nothing
+                        // listens behind yet. dockerd only starts later, the worker waits for it on its own.
+                        "readinessProbe": { "tcpSocket": { "port": 22 }, "periodSeconds": 2 },
+                        "resources": { "requests": resources, "limits": resources },
+                        "volumeMounts": [{ "name": "docker", "mountPath": "/var/lib/docker" }],
+                    }],
+                    // overlay2 does not work on top of the container's overlayfs
+                    "volumes": [{ "name": "docker", "emptyDir": {} }],
+                },
+            });
+            self.kubectl(&["create", "-f", "-"], Some(&pod.to_string())).await?;
+
+            if self.expose == Expose::LoadBalancer {
+                let service = json!({
+                    "apiVersion": "v1",
+                    "kind": "Service",
+                    "metadata": { "name": name, "labels": labels },
+                    "spec": {
+                        "type": "LoadBalancer",
+                        "selector": { INSTANCE_LABEL: name },
+                        "ports": [
+                            { "name": "ssh", "port": 22 },
+                            { "name": "docker", "port": 2376 },
+                        ],
+                    },
+                });
+                self.kubectl(&["create", "-f", "-"], Some(&service.to_string())).await?;
+            }
+        }
+        log::info!("{name}");
+
+        // From here on, the pod must be deleted when the run ends
+        let delete = {
+            let (kubernetes, name) = (self.clone(), name.clone());
+            DestroyGuard::new(async move {
+                kubernetes.kubectl(&["delete", "pod", &name, "--wait=false"], None).await?;
+                if kubernetes.expose == Expose::LoadBalancer {
+                    kubernetes.kubectl(&["delete", "service", &name, "--wait=false"], None).await?;
+                }
+                Ok(())
+            })
+        };
+        let exposed = async {
+            let step = step.next();
+            log::info!("Waiting for pod to become ready...");
+            self.kubectl(&["wait", "--for=condition=Ready", &format!("pod/{name}"), "--timeout=300s"], None)
+                .await?;
+
+            let step = step.next();
+            log::info!("Exposing ports...");
+            let exposed = match self.expose {
+                Expose::PortForward => {
+                    let (address, port_forward) = self.port_forward(&name).await?;
+                    (address, Some(port_forward))
+                }
+                Expose::LoadBalancer => (self.load_balancer_ip(&name).await?.into(), None),
+            };
+
+            steps::end(step);
+            anyhow::Ok(exposed)
+        }
+        .await;
+
+        match exposed {
+            Ok((address, port_forward)) => {
+                let delete = delete.disarm();
+                let destroy = async move {
+                    drop(port_forward); // kills it
+                    delete.await
+                }
+                .boxed();
+                Ok(SpawnedVm { address, destroy: Some(destroy), interruption_notice_command: None })
+            }
+            Err(e) => {
+                log::info!("Deleting pod after failed start...");
+                if let Err(e) = delete.disarm().await {
+                    log::error!("Failed to delete pod: {e:#}");
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn teardown_command(&self) -> &str {
+        // The container stops when user_data, its main process, exits
+        "true"
+    }
+}
+
+impl Kubernetes {
+    /// Runs kubectl with the configured context and namespace, returns stdout.
+    async fn kubectl(&self, args: &[&str], stdin: Option<&str>) -> anyhow::Result<Vec<u8>> {
+        log::debug!("kubectl {}", args.join(" "));
+        let mut child = self
+            .kubectl_command(args)
+            .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let Some(stdin) = stdin {
+            let mut child_stdin = child.stdin.take().expect("piped stdin");
+            child_stdin.write_all(stdin.as_bytes()).await?;
+        }
+        let output = child.wait_with_output().await?;
+        if !output.status.success() {
+            anyhow::bail!("kubectl {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(output.stdout)
+    }
+
+    async fn kubectl_json<T: DeserializeOwned>(&self, args: &[&str]) -> anyhow::Result<T> {
+        Ok(serde_json::from_slice(&self.kubectl(args, None).await?)?)
+    }
+
+    fn kubectl_command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new("kubectl");
+        if let Some(context) = &self.context {
+            command.args(["--context", context]);
+        }
+        if let Some(namespace) = &self.namespace {
+            command.args(["--namespace", namespace]);
+        }
+        command.args(args);
+        command
+    }
+
+    /// Forwards free local ports to the pod, for as long as the returned child lives.
+    async fn port_forward(&self, name: &str) -> anyhow::Result<(VmAddress, tokio::process::Child)> {
+        let mut child = self
+            .kubectl_command(&["port-forward", "--address", "127.0.0.1", &format!("pod/{name}"), ":22", ":2376"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        // Prints e.g. "Forwarding from 127.0.0.1:40425 -> 22" for each port, then a line per connection.
+        let mut lines = BufReader::new(child.stdout.take().expect("piped stdout")).lines();
+        let mut local_ports = HashMap::new();
+        while local_ports.len() < 2 {
+            let line = lines.next_line().await?.ok_or(anyhow::format_err!("kubectl port-forward exited"))?;
+            log::debug!("{line}");
+            if let Some((local, remote)) = line.strip_prefix("Forwarding from 127.0.0.1:").and_then(|s| s.split_once(" -> ")) {
+                local_ports.insert(remote.parse::<u16>()?, local.parse::<u16>()?);
+            }
+        }
+        tokio::spawn(async move {
+            while let Ok(Some(line)) = lines.next_line().await {
+                log::debug!("{line}");
+            }
+        });
+
+        let address = VmAddress { ip: Ipv4Addr::LOCALHOST, ssh_port: local_ports[&22], docker_port: local_ports[&2376] };
+        Ok((address, child))
+    }
+
+    async fn load_balancer_ip(&self, name: &str) -> anyhow::Result<Ipv4Addr> {
+        let deadline = SystemTime::now() + Duration::from_secs(300);
+        loop {
+            if SystemTime::now() > deadline {
+                anyhow::bail!("load balancer was not provisioned in time limit");
+            }
+            let service: Service = self.kubectl_json(&["get", "service", name, "-o", "json"]).await?;
+            match service.status.load_balancer.ingress.into_iter().next() {
+                Some(Ingress { ip: Some(ip), .. }) => break Ok(ip.parse()?),
+                Some(Ingress { hostname: Some(hostname), .. }) => {
+                    // e.g. AWS ELB, whose DNS name may take a while to resolve
+                    match lookup_host((hostname.as_str(), 22)).await {
+                        Ok(addrs) => {
+                            if let Some(IpAddr::V4(ip)) = addrs.map(|addr| addr.ip()).find(IpAddr::is_ipv4) {
+                                break Ok(ip);
+                            }
+                        }
+                        Err(e) => log::debug!("Resolving {hostname} failed: {e:#}"),
+                    }
+                }
+                _ => {}
+            }
+            sleep(Duration::from_secs(2)).await;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct List<T> {
+    items: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Pod {
+    metadata: Metadata,
+    status: PodStatus,
+}
+
+#[derive(Deserialize)]
+struct PodStatus {
+    phase: String,
+}
+
+#[derive(Deserialize)]
+struct Service {
+    metadata: Metadata,
+    #[serde(default)]
+    status: ServiceStatus,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct ServiceStatus {
+    #[serde(default)]
+    load_balancer: LoadBalancerStatus,
+}
+
+#[derive(Deserialize, Default)]
+struct LoadBalancerStatus {
+    #[serde(default)]
+    ingress: Vec<Ingress>,
+}
+
+#[derive(Deserialize)]
+struct Ingress {
+    ip: Option<String>,
+    hostname: Option<String>,
+}
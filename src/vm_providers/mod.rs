@@ -1,44 +1,393 @@
+mod aliyun;
+pub use aliyun::Aliyun;
+
+mod azure;
+pub use azure::Azure;
+
+mod container;
+pub use container::Container;
+
+mod digitalocean;
+pub use digitalocean::DigitalOcean;
+
 mod ec2;
 pub use ec2::Ec2;
 
+mod firecracker;
+pub use firecracker::Firecracker;
+
 mod gce;
 pub use gce::Gce;
 
+mod hetzner;
+pub use hetzner::Hetzner;
+
+mod incus;
+pub use incus::Incus;
+
+mod kubernetes;
+pub use kubernetes::Kubernetes;
+
 mod multipass;
 pub use multipass::Multipass;
 
+mod nspawn;
+pub use nspawn::Nspawn;
+
+mod oci;
+pub use oci::Oci;
+
+mod openstack;
+pub use openstack::OpenStack;
+
+mod plugin;
+pub use plugin::Plugin;
+
+mod proxmox;
+pub use proxmox::Proxmox;
+
+mod qemu;
+pub use qemu::Qemu;
+
+mod ssh;
+pub use ssh::Ssh;
+
+mod bearer_client;
+mod run_dirs;
+
 use async_trait::async_trait;
-use clap::{Args, Subcommand};
-use std::net::Ipv4Addr;
+use clap::{Arg, ArgAction, ArgMatches, Args, Command, FromArgMatches, Subcommand};
+use futures::{future::BoxFuture, FutureExt as _};
+use rand::{distributions::Alphanumeric, Rng};
+use std::{
+    future::Future,
+    net::{Ipv4Addr, SocketAddrV4},
+};
+
+/// Where the worker can reach sshd and dockerd of a spawned VM.
+#[derive(Debug, Clone, Copy)]
+pub struct VmAddress {
+    pub ip: Ipv4Addr,
+    pub ssh_port: u16,
+    pub docker_port: u16,
+}
+
+impl VmAddress {
+    pub fn ssh(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(self.ip, self.ssh_port)
+    }
+
+    pub fn docker(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(self.ip, self.docker_port)
+    }
+}
 
-/// A provider must define its specific CLI args and be able to spawn the VM.
+/// VM reachable directly, on the ports it listens on.
+impl From<Ipv4Addr> for VmAddress {
+    fn from(ip: Ipv4Addr) -> Self {
+        Self { ip, ssh_port: 22, docker_port: 2376 }
+    }
+}
+
+/// A VM returned by a provider.
+pub struct SpawnedVm {
+    pub address: VmAddress,
+
+    /// Deletes the VM from the launcher side once the run ends (or setup fails),
+    /// for providers whose VMs cannot delete themselves on shutdown.
+    pub destroy: Option<BoxFuture<'static, anyhow::Result<()>>>,
+
+    /// Shell command the worker runs on interruptible VMs (e.g. spot instances) while they are in use.
+    /// It prints a notice and exits once the provider is about to interrupt the VM.
+    pub interruption_notice_command: Option<&'static str>,
+}
+
+impl From<VmAddress> for SpawnedVm {
+    fn from(address: VmAddress) -> Self {
+        Self { address, destroy: None, interruption_notice_command: None }
+    }
+}
+
+impl From<Ipv4Addr> for SpawnedVm {
+    fn from(ip: Ipv4Addr) -> Self {
+        VmAddress::from(ip).into()
+    }
+}
+
+/// Destroy hook of a VM that is still starting up. Should `spawn` be dropped before it hands the hook over, e.g. on
+/// SIGINT or when another `--hedge` candidate wins, the hook is deferred to `shutdown::destroy_pending` instead of
+/// leaking the VM.
+struct DestroyGuard(Option<BoxFuture<'static, anyhow::Result<()>>>);
+
+impl DestroyGuard {
+    fn new(destroy: impl Future<Output = anyhow::Result<()>> + Send + 'static) -> Self {
+        Self(Some(destroy.boxed()))
+    }
+
+    /// Takes the hook back, to hand it over in `SpawnedVm::destroy` or run it after a failed start.
+    fn disarm(mut self) -> BoxFuture<'static, anyhow::Result<()>> {
+        self.0.take().expect("disarmed once")
+    }
+}
+
+impl Drop for DestroyGuard {
+    fn drop(&mut self) {
+        if let Some(destroy) = self.0.take() {
+            crate::shutdown::defer_destroy(destroy);
+        }
+    }
+}
+
+/// A provider must define its specific CLI args (plugins describe them at runtime) and be able to spawn the VM.
 #[async_trait]
-pub trait VmProvider: Args + Clone {
-    /// Currently we expects Ubuntu 24.04 (Noble Numbat) on arm64 or amd64
-    async fn spawn(&self, user_data: &str) -> anyhow::Result<Ipv4Addr>;
+pub trait VmProvider: Clone {
+    /// Currently we expects Ubuntu (24.04 Noble Numbat, unless overridden by the user) on arm64 or amd64
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm>;
+
+    /// Shell command(s) run as root by `user_data` once keepalives stop.
+    /// VMs power off, after which the provider (or its garbage collection) deletes them.
+    fn teardown_command(&self) -> &str {
+        "shutdown -h now"
+    }
 }
 
-#[derive(Args, Clone)]
+/// One of the built-in providers, or a plugin found on PATH,
+/// optionally followed by fallbacks that are tried in order when spawning fails.
+///
+/// May also carry hedges, which the worker launches concurrently, see `hedged`.
+#[derive(Clone)]
 pub struct SomeVmProvider {
-    #[command(subcommand)]
+    /// Provider name or '--fallback'/'--hedge' value, for logging.
+    label: String,
     inner: SomeVmProviderEnum,
+    fallbacks: Vec<Alternative>,
+    hedges: Vec<Alternative>,
+}
+
+/// Provider given as a '--fallback' or '--hedge' value.
+#[derive(Clone)]
+struct Alternative {
+    spec: String,
+    provider: SomeVmProviderEnum,
+}
+
+impl Alternative {
+    fn parse_all(matches: &ArgMatches, id: &str) -> Result<Vec<Self>, clap::Error> {
+        matches
+            .get_many::<String>(id)
+            .unwrap_or_default()
+            .map(|spec| {
+                let words = spec.split_whitespace();
+                let alternative_matches = SomeVmProviderEnum::augment_with_plugins(Command::new(format!("--{id}")).no_binary_name(true))
+                    .subcommand_required(true)
+                    .try_get_matches_from(words)?;
+                let provider = SomeVmProviderEnum::from_arg_matches_with_plugins(&alternative_matches)?;
+                Ok(Self { spec: spec.clone(), provider })
+            })
+            .collect()
+    }
+}
+
+impl SomeVmProvider {
+    /// This provider (with its fallbacks) followed by each hedge, to be raced against each other.
+    pub fn hedged(&self) -> Vec<SomeVmProvider> {
+        let primary = Self { hedges: Vec::new(), ..self.clone() };
+        let hedges =
+            self.hedges
+                .iter()
+                .map(|hedge| Self { label: hedge.spec.clone(), inner: hedge.provider.clone(), fallbacks: Vec::new(), hedges: Vec::new() });
+        std::iter::once(primary).chain(hedges).collect()
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+}
+
+impl FromArgMatches for SomeVmProvider {
+    fn from_arg_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        let inner = SomeVmProviderEnum::from_arg_matches_with_plugins(matches)?;
+        let label = matches.subcommand_name().unwrap_or_default().to_owned();
+        let fallbacks = Alternative::parse_all(matches, "fallback")?;
+        let hedges = Alternative::parse_all(matches, "hedge")?;
+        Ok(Self { label, inner, fallbacks, hedges })
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> Result<(), clap::Error> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+impl Args for SomeVmProvider {
+    fn augment_args(cmd: Command) -> Command {
+        SomeVmProviderEnum::augment_with_plugins(cmd)
+            .subcommand_required(true)
+            .arg_required_else_help(true)
+            .arg(
+                Arg::new("fallback")
+                    .long("fallback")
+                    .value_name("PROVIDER [OPTIONS]")
+                    .action(ArgAction::Append)
+                    .global(true)
+                    .help("Provider and its options to try if spawning fails, e.g. 'ec2 --region us-west-2'. Can be repeated."),
+            )
+            .arg(
+                Arg::new("hedge")
+                    .long("hedge")
+                    .value_name("PROVIDER [OPTIONS]")
+                    .action(ArgAction::Append)
+                    .global(true)
+                    .help("Provider and its options to launch on concurrently. The first instance with a reachable dockerd is used, the others are terminated. Can be repeated."),
+            )
+    }
+
+    fn augment_args_for_update(cmd: Command) -> Command {
+        Self::augment_args(cmd)
+    }
 }
 
 #[derive(Subcommand, Clone)]
 #[command(subcommand_help_heading = "Providers", subcommand_value_name = "PROVIDER", disable_help_subcommand = true)]
 enum SomeVmProviderEnum {
+    Aliyun(Aliyun),
+    Azure(Azure),
+    Container(Container),
+    #[command(name = "digitalocean")]
+    DigitalOcean(DigitalOcean),
     Ec2(Ec2),
+    Firecracker(Firecracker),
     Gce(Gce),
+    Hetzner(Hetzner),
+    Incus(Incus),
+    Kubernetes(Kubernetes),
     Multipass(Multipass),
+    Nspawn(Nspawn),
+    Oci(Oci),
+    #[command(name = "openstack")]
+    OpenStack(OpenStack),
+    Proxmox(Proxmox),
+    Qemu(Qemu),
+    Ssh(Ssh),
+    #[command(skip)]
+    Plugin(Plugin),
+}
+
+impl SomeVmProviderEnum {
+    /// Built-in providers and the plugins on PATH, as subcommands of `cmd`.
+    fn augment_with_plugins(cmd: Command) -> Command {
+        Self::augment_subcommands(cmd).subcommands(plugin::discover(Self::has_subcommand).iter().map(|description| description.command()))
+    }
+
+    fn from_arg_matches_with_plugins(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        if let Some((name, sub_matches)) = matches.subcommand() {
+            if let Some(description) = plugin::discover(Self::has_subcommand).iter().find(|description| description.name() == name) {
+                return Ok(Self::Plugin(Plugin::from_arg_matches(description.clone(), sub_matches)));
+            }
+        }
+        Self::from_arg_matches(matches)
+    }
 }
 
 #[async_trait]
 impl VmProvider for SomeVmProvider {
-    async fn spawn(&self, user_data: &str) -> anyhow::Result<Ipv4Addr> {
-        match &self.inner {
+    /// Unlike other providers, expects `{{teardown_command}}` in `user_data` to be left for it to fill in,
+    /// since fallbacks may tear down differently.
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
+        plugin::warn_skipped();
+        let render = |provider: &SomeVmProviderEnum| user_data.replace("{{teardown_command}}", provider.teardown_command());
+        let mut result = self.inner.spawn(&render(&self.inner)).await;
+        for fallback in &self.fallbacks {
+            match result {
+                Ok(_) => break,
+                Err(e) => {
+                    log::warn!("Spawning failed: {e:#}");
+                    log::warn!("Falling back to '{}'...", fallback.spec);
+                    result = fallback.provider.spawn(&render(&fallback.provider)).await;
+                }
+            }
+        }
+        result
+    }
+
+    fn teardown_command(&self) -> &str {
+        self.inner.teardown_command()
+    }
+}
+
+#[async_trait]
+impl VmProvider for SomeVmProviderEnum {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
+        match self {
+            SomeVmProviderEnum::Aliyun(p) => p.spawn(user_data).await,
+            SomeVmProviderEnum::Azure(p) => p.spawn(user_data).await,
+            SomeVmProviderEnum::Container(p) => p.spawn(user_data).await,
+            SomeVmProviderEnum::DigitalOcean(p) => p.spawn(user_data).await,
             SomeVmProviderEnum::Ec2(p) => p.spawn(user_data).await,
+            SomeVmProviderEnum::Firecracker(p) => p.spawn(user_data).await,
             SomeVmProviderEnum::Gce(p) => p.spawn(user_data).await,
+            SomeVmProviderEnum::Hetzner(p) => p.spawn(user_data).await,
+            SomeVmProviderEnum::Incus(p) => p.spawn(user_data).await,
+            SomeVmProviderEnum::Kubernetes(p) => p.spawn(user_data).await,
             SomeVmProviderEnum::Multipass(p) => p.spawn(user_data).await,
+            SomeVmProviderEnum::Nspawn(p) => p.spawn(user_data).await,
+            SomeVmProviderEnum::Oci(p) => p.spawn(user_data).await,
+            SomeVmProviderEnum::OpenStack(p) => p.spawn(user_data).await,
+            SomeVmProviderEnum::Proxmox(p) => p.spawn(user_data).await,
+            SomeVmProviderEnum::Qemu(p) => p.spawn(user_data).await,
+            SomeVmProviderEnum::Ssh(p) => p.spawn(user_data).await,
+            SomeVmProviderEnum::Plugin(p) => p.spawn(user_data).await,
+        }
+    }
+
+    fn teardown_command(&self) -> &str {
+        match self {
+            SomeVmProviderEnum::Aliyun(p) => p.teardown_command(),
+            SomeVmProviderEnum::Azure(p) => p.teardown_command(),
+            SomeVmProviderEnum::Container(p) => p.teardown_command(),
+            SomeVmProviderEnum::DigitalOcean(p) => p.teardown_command(),
+            SomeVmProviderEnum::Ec2(p) => p.teardown_command(),
+            SomeVmProviderEnum::Firecracker(p) => p.teardown_command(),
+            SomeVmProviderEnum::Gce(p) => p.teardown_command(),
+            SomeVmProviderEnum::Hetzner(p) => p.teardown_command(),
+            SomeVmProviderEnum::Incus(p) => p.teardown_command(),
+            SomeVmProviderEnum::Kubernetes(p) => p.teardown_command(),
+            SomeVmProviderEnum::Multipass(p) => p.teardown_command(),
+            SomeVmProviderEnum::Nspawn(p) => p.teardown_command(),
+            SomeVmProviderEnum::Oci(p) => p.teardown_command(),
+            SomeVmProviderEnum::OpenStack(p) => p.teardown_command(),
+            SomeVmProviderEnum::Proxmox(p) => p.teardown_command(),
+            SomeVmProviderEnum::Qemu(p) => p.teardown_command(),
+            SomeVmProviderEnum::Ssh(p) => p.teardown_command(),
+            SomeVmProviderEnum::Plugin(p) => p.teardown_command(),
         }
     }
 }
+
+/// Unique name for an instance, `fleeting-<pid>-<random>`.
+/// Providers that need to collect garbage rely on the `fleeting-` prefix.
+fn instance_name() -> String {
+    format!(
+        "fleeting-{}-{}",
+        std::process::id(),
+        // for dedup across hosts running fleeting:
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(8)
+            .map(char::from)
+            .collect::<String>()
+            .to_lowercase()
+    )
+}
+
+/// `allow_cidrs`, or else this host's public IPv4 address as seen from the internet,
+/// for allowing inbound traffic to the VM from the worker only.
+async fn allowed_source_ranges(allow_cidrs: &[String]) -> anyhow::Result<Vec<String>> {
+    if !allow_cidrs.is_empty() {
+        return Ok(allow_cidrs.to_vec());
+    }
+    log::debug!("Detecting public IP...");
+    let body = reqwest::get("https://checkip.amazonaws.com").await?.error_for_status()?.text().await?;
+    let ip: Ipv4Addr = body.trim().parse().map_err(|_| anyhow::format_err!("unexpected public IP: {body:?}"))?;
+    Ok(vec![format!("{ip}/32")])
+}
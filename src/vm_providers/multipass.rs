@@ -1,5 +1,5 @@
-use super::VmProvider;
-use crate::{command_ext::CommandExt, steps};
+use super::{SpawnedVm, VmProvider};
+use crate::{command_ext::CommandExt, steps, ubuntu_release::UbuntuRelease};
 use async_trait::async_trait;
 use base64::prelude::*;
 use clap::Args;
@@ -22,6 +22,14 @@ itself. To get started, install multipass as described on:
     https://multipass.run/install
 "#},)]
 pub struct Multipass {
+    /// Image name or release alias as listed by 'multipass find', or an image URL [default: --ubuntu-release]
+    #[arg(long, conflicts_with = "ubuntu_release")]
+    image: Option<String>,
+
+    /// Ubuntu release.
+    #[arg(long, default_value = "24.04")]
+    ubuntu_release: UbuntuRelease,
+
     /// CPUs.
     #[arg(long)]
     cpus: Option<usize>,
@@ -37,7 +45,7 @@ pub struct Multipass {
 
 #[async_trait]
 impl VmProvider for Multipass {
-    async fn spawn(&self, user_data: &str) -> anyhow::Result<Ipv4Addr> {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
         let step = steps::start();
         log::info!("Checking multipass installation...");
         {
@@ -53,7 +61,7 @@ impl VmProvider for Multipass {
             log::debug!("{version:?}");
         }
 
-        let step: _ = step.next();
+        let step = step.next();
         log::info!("Purging old stopped fleeting VMs...");
         {
             let vms = multipass_list().await?;
@@ -72,7 +80,7 @@ impl VmProvider for Multipass {
             }
         }
 
-        let step: _ = step.next();
+        let step = step.next();
         log::info!("Launching a VM...");
         let name = format!("fleeting-{}", std::process::id());
         {
@@ -90,8 +98,9 @@ impl VmProvider for Multipass {
                 BASE64_STANDARD.encode(user_data)
             );
 
+            let image = self.image.clone().unwrap_or_else(|| self.ubuntu_release.to_string());
             let mut command = Command::new("multipass");
-            command.args(["launch", "--name", &name, "--cloud-init", "-", "24.04"]);
+            command.args(["launch", "--name", &name, "--cloud-init", "-", &image]);
             if let Some(cpus) = self.cpus {
                 command.args(["--cpus", &cpus.to_string()]);
             }
@@ -129,7 +138,7 @@ impl VmProvider for Multipass {
             }
         }
 
-        let step: _ = step.next();
+        let step = step.next();
         log::info!("Getting VM IP...");
         let ip = {
             let vms = multipass_list().await?;
@@ -141,7 +150,7 @@ impl VmProvider for Multipass {
         };
 
         steps::end(step);
-        Ok(ip)
+        Ok(ip.into())
     }
 }
 
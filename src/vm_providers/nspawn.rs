@@ -0,0 +1,362 @@
+use super::{
+    instance_name,
+    qemu::download,
+    run_dirs::{cache_dir, RunDirs},
+    SpawnedVm, VmProvider,
+};
+use crate::{arch::Arch, command_ext::CommandExt as _, steps, ubuntu_release::UbuntuRelease};
+use anyhow::Context as _;
+use async_trait::async_trait;
+use clap::Args;
+use indoc::formatdoc;
+use rand::{distributions::Alphanumeric, Rng as _};
+use std::{
+    collections::HashSet,
+    fs,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    process::Stdio,
+    str::FromStr as _,
+    time::{Duration, SystemTime},
+};
+use tokio::{process::Command, time::sleep};
+
+/// Containers get a /30 out of this /16: the host side of their veth is .1, the container .2.
+const SUBNET: &str = "10.213.0.0/16";
+
+/// Of the host side of containers' veths, which fleeting deletes if their container is gone.
+const HOST_INTERFACE_PREFIX: &str = "vf-";
+
+/// systemd-nspawn container booting Ubuntu (local)
+#[derive(Args, Clone)]
+#[command(
+    override_usage = color_print::cstr! {r#"<bold>fleeting</bold> <bold>nspawn</bold> [OPTIONS] [COMMAND]...
+
+Boots a copy of the Ubuntu cloud root filesystem for the host architecture with
+'systemd-nspawn --boot', on a private veth link to the host. The user_data
+runs as a unit on first boot. Containers get a /30 of 10.213.0.0/16, which is
+masqueraded for internet access.
+
+No daemon is involved: the container lives as long as its systemd-nspawn
+process, which exits once the user_data powers it off. Running containers can
+be managed with machinectl if systemd-machined is running.
+
+<bold><underline>Requirements:</underline></bold>
+  - Linux host, running fleeting as root
+  - systemd-nspawn (e.g. the systemd-container package), tar with xz, iptables
+
+The root filesystem is cached in the user's cache directory (e.g. ~/.cache/fleeting/nspawn).
+Delete it to download the latest release.
+
+<bold><underline>Limitations:</underline></bold>
+Containers outlive the run, so the host networking they need is left in place:
+IP forwarding stays enabled, and the iptables rules masquerading and accepting
+forwarded traffic for 10.213.0.0/16 stay at the top of the nat POSTROUTING and
+filter FORWARD chains. Remove them with 'iptables -D' if needed.
+
+"#},)]
+pub struct Nspawn {
+    /// Release of Canonical's Ubuntu image.
+    #[arg(long, default_value = "24.04")]
+    ubuntu_release: UbuntuRelease,
+}
+
+#[async_trait]
+impl VmProvider for Nspawn {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
+        let step = steps::start();
+        log::info!("Checking systemd-nspawn installation...");
+        {
+            let version = Command::new("systemd-nspawn").arg("--version").capture_stdout().await?;
+            log::debug!("{}", String::from_utf8_lossy(&version).lines().next().unwrap_or_default());
+            let uid = Command::new("id").arg("-u").capture_stdout().await?;
+            if String::from_utf8_lossy(&uid).trim() != "0" {
+                anyhow::bail!("the nspawn provider must run as root");
+            }
+        }
+        let arch = Arch::from_str(std::env::consts::ARCH)?;
+        let cache_dir = cache_dir("nspawn")?;
+        let run_dirs = RunDirs::new("nspawn", "systemd-nspawn")?;
+
+        let step = step.next();
+        log::info!("Purging old stopped fleeting containers...");
+        {
+            let purged = run_dirs.purge_stopped()?;
+            log::info!("{purged} purged");
+
+            // Host veths normally disappear with their container's network namespace. Listed before the claims,
+            // which are written before the interface exists, so concurrent launches keep theirs.
+            let links = Command::new("ip").args(["-o", "link", "show"]).capture_stdout().await?;
+            let in_use = run_dirs
+                .list()?
+                .into_iter()
+                .filter_map(|run_dir| fs::read_to_string(run_dir.join("interface")).ok())
+                .collect::<HashSet<_>>();
+            for link in String::from_utf8_lossy(&links).lines() {
+                // e.g. "7: vf-abcdefgh@if2: <BROADCAST,..."
+                let Some(interface) = link.split(": ").nth(1).and_then(|name| name.split('@').next()) else {
+                    continue;
+                };
+                if interface.starts_with(HOST_INTERFACE_PREFIX) && !in_use.contains(interface) {
+                    log::debug!("Deleting leftover {interface}");
+                    Command::new("ip").args(["link", "delete", interface]).capture_stdout().await?;
+                }
+            }
+        }
+
+        let step = step.next();
+        log::info!("Downloading Ubuntu root filesystem if needed...");
+        let base_rootfs = {
+            let path = cache_dir.join(format!("ubuntu-{}-{}-rootfs", self.ubuntu_release, arch.as_dpkg()));
+            if path.exists() {
+                log::info!("{path:?} (cached)");
+            } else {
+                let file_name = format!("ubuntu-{}-server-cloudimg-{}-root.tar.xz", self.ubuntu_release, arch.as_dpkg());
+                let tarball = cache_dir.join(&file_name);
+                let url = format!("https://cloud-images.ubuntu.com/releases/{}/release/{file_name}", self.ubuntu_release);
+                download(&url, &tarball).await.with_context(|| format!("downloading {url}"))?;
+
+                log::debug!("Unpacking {tarball:?}...");
+                let partial_path = PathBuf::from(format!("{}.partial", path.display()));
+                if partial_path.exists() {
+                    fs::remove_dir_all(&partial_path)?;
+                }
+                fs::create_dir(&partial_path)?;
+                Command::new("tar")
+                    .args(["--extract", "--xz", "--numeric-owner", "--file"])
+                    .arg(&tarball)
+                    .arg("--directory")
+                    .arg(&partial_path)
+                    .capture_stdout()
+                    .await
+                    .context("unpacking root filesystem")?;
+                fs::rename(&partial_path, &path)?;
+                fs::remove_file(&tarball)?;
+                log::info!("{path:?} (downloaded)");
+            }
+            path
+        };
+
+        let step = step.next();
+        log::info!("Configuring host networking if needed...");
+        {
+            fs::write("/proc/sys/net/ipv4/ip_forward", "1").context("enabling IP forwarding")?;
+            // Docker on the host sets the FORWARD policy to DROP
+            let rules: [&[&str]; 3] = [
+                &["-t", "nat", "POSTROUTING", "-s", SUBNET, "!", "-d", SUBNET, "-j", "MASQUERADE"],
+                &["-t", "filter", "FORWARD", "-s", SUBNET, "-j", "ACCEPT"],
+                &["-t", "filter", "FORWARD", "-d", SUBNET, "-j", "ACCEPT"],
+            ];
+            let mut added = 0;
+            for rule in rules {
+                let (table, chain, spec) = (&rule[..2], rule[2], &rule[3..]);
+                let exists = Command::new("iptables")
+                    .args(table)
+                    .arg("-C")
+                    .arg(chain)
+                    .args(spec)
+                    .stderr(Stdio::null())
+                    .status()
+                    .await?;
+                if !exists.success() {
+                    Command::new("iptables").args(table).arg("-I").arg(chain).args(spec).capture_stdout().await?;
+                    added += 1;
+                }
+            }
+            log::info!("{added} iptables rules added");
+        }
+
+        let step = step.next();
+        log::info!("Launching a container...");
+        let name = instance_name();
+        let guest_ip = {
+            let run_dir = run_dirs.create(&name)?;
+
+            let (host_ip, guest_ip) = pick_link_network().await?;
+            // Interface names are limited to 15 characters
+            let host_interface = format!(
+                "{HOST_INTERFACE_PREFIX}{}",
+                rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(8)
+                    .map(char::from)
+                    .collect::<String>()
+                    .to_lowercase()
+            );
+            // Claims the interface before it exists, see purging above
+            fs::write(run_dir.join("interface"), &host_interface)?;
+
+            log::debug!("Copying root filesystem...");
+            let rootfs = run_dir.join("rootfs");
+            Command::new("cp")
+                .args(["--archive", "--reflink=auto"])
+                .arg(&base_rootfs)
+                .arg(&rootfs)
+                .capture_stdout()
+                .await
+                .context("copying root filesystem")?;
+            prepare_rootfs(&rootfs, &name, user_data, host_ip, guest_ip)?;
+
+            let mut command = Command::new("systemd-nspawn");
+            command
+                .args(["--quiet", "--boot", "--console=read-only"])
+                .arg(format!("--machine={name}"))
+                .arg("--directory")
+                .arg(&rootfs)
+                .arg(format!("--network-veth-extra={host_interface}:eth0"))
+                .arg("--private-network")
+                .arg("--resolv-conf=off")
+                // For dockerd
+                .args(["--capability=all", "--system-call-filter=@keyring bpf"]);
+            if !Path::new("/run/systemd/machines").exists() {
+                // Registering needs systemd-machined
+                command.arg("--register=no");
+            }
+            // Passed to the container's systemd, which treats them like kernel command line options
+            command.args(["systemd.wants=systemd-networkd.service", "systemd.wants=fleeting-user-data.service"]);
+            let log_path = run_dir.join("console.log");
+            let mut child = command
+                .stdin(Stdio::null())
+                .stdout(fs::File::create(&log_path)?)
+                .stderr(fs::File::create(run_dir.join("nspawn.err"))?)
+                .detached()
+                .spawn()?;
+            fs::write(run_dir.join("pid"), child.id().expect("child pid").to_string())?;
+
+            log::debug!("Configuring {host_interface}...");
+            let deadline = SystemTime::now() + Duration::from_secs(30);
+            loop {
+                if let Some(exit_status) = child.try_wait()? {
+                    let stderr = fs::read_to_string(run_dir.join("nspawn.err")).unwrap_or_default();
+                    anyhow::bail!("systemd-nspawn exited with {exit_status:?}: {} (see {log_path:?})", stderr.trim());
+                }
+                if SystemTime::now() > deadline {
+                    anyhow::bail!("{host_interface} did not appear in time limit");
+                }
+                let exists = Command::new("ip")
+                    .args(["link", "show", &host_interface])
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()
+                    .await?;
+                if exists.success() {
+                    break;
+                }
+                sleep(Duration::from_millis(200)).await;
+            }
+            Command::new("ip")
+                .args(["address", "add", &format!("{host_ip}/30"), "dev", &host_interface])
+                .capture_stdout()
+                .await?;
+            Command::new("ip").args(["link", "set", &host_interface, "up"]).capture_stdout().await?;
+            guest_ip
+        };
+        log::info!("{name}");
+
+        steps::end(step);
+        Ok(guest_ip.into())
+    }
+}
+
+/// A random /30 of `SUBNET` that doesn't overlap any address or route on the host yet, as host and guest IP.
+async fn pick_link_network() -> anyhow::Result<(Ipv4Addr, Ipv4Addr)> {
+    let mut taken = Vec::new();
+    for args in [&["-4", "-o", "address", "show"][..], &["-4", "route", "show", "table", "all"]] {
+        let output = Command::new("ip").args(args).capture_stdout().await?;
+        taken.extend(String::from_utf8_lossy(&output).split_whitespace().filter_map(parse_network));
+    }
+    for _ in 0..100 {
+        // Avoiding .0.0/30 so the network address of the /16 stays unused
+        let offset = rand::thread_rng().gen_range(1..(1 << 14)) * 4;
+        let network = Ipv4Addr::new(10, 213, (offset >> 8) as u8, (offset & 0xff) as u8);
+        if is_free(network, &taken) {
+            return Ok((Ipv4Addr::from(u32::from(network) + 1), Ipv4Addr::from(u32::from(network) + 2)));
+        }
+    }
+    anyhow::bail!("no free /30 found in {SUBNET}, see 'ip -4 address' and 'ip -4 route'")
+}
+
+/// `10.0.0.1/8` or a bare `10.0.0.1` as `/32`.
+fn parse_network(word: &str) -> Option<(Ipv4Addr, u32)> {
+    let (ip, prefix_len) = word.split_once('/').unwrap_or((word, "32"));
+    Some((ip.parse().ok()?, prefix_len.parse().ok().filter(|prefix_len| *prefix_len <= 32)?))
+}
+
+/// Whether the /30 at `network` overlaps none of the `taken` networks at least as specific as `SUBNET`.
+/// Broader ones like a VPN's 10.0.0.0/8 are ignored, since the /30 is routed more specifically.
+fn is_free(network: Ipv4Addr, taken: &[(Ipv4Addr, u32)]) -> bool {
+    taken.iter().filter(|(_, prefix_len)| *prefix_len >= 16).all(|(ip, prefix_len)| {
+        let mask = u32::MAX << (32 - prefix_len.min(&30));
+        (u32::from(*ip) ^ u32::from(network)) & mask != 0
+    })
+}
+
+/// Configures the copied root filesystem: static network, a unit running `user_data`, and no cloud-init.
+fn prepare_rootfs(rootfs: &Path, name: &str, user_data: &str, host_ip: Ipv4Addr, guest_ip: Ipv4Addr) -> anyhow::Result<()> {
+    fs::write(rootfs.join("etc/hostname"), format!("{name}\n"))?;
+    fs::write(rootfs.join("etc/cloud/cloud-init.disabled"), "")?;
+
+    // The host's resolv.conf may point to a stub resolver on its loopback
+    let nameservers = ["/run/systemd/resolve/resolv.conf", "/etc/resolv.conf"]
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.strip_prefix("nameserver"))
+        .map(str::trim)
+        .filter(|nameserver| nameserver.parse::<Ipv4Addr>().is_ok_and(|ip| !ip.is_loopback()))
+        .map(|nameserver| format!("DNS={nameserver}\n"))
+        .collect::<String>();
+    fs::write(
+        rootfs.join("etc/systemd/network/10-fleeting.network"),
+        formatdoc! {"
+            [Match]
+            Name=eth0
+
+            [Network]
+            Address={guest_ip}/30
+            Gateway={host_ip}
+            {nameservers}"
+        },
+    )?;
+
+    fs::create_dir_all(rootfs.join("var/lib/fleeting"))?;
+    fs::write(rootfs.join("var/lib/fleeting/user-data"), user_data)?;
+    fs::write(
+        rootfs.join("etc/systemd/system/fleeting-user-data.service"),
+        formatdoc! {"
+            [Unit]
+            Description=fleeting user_data
+            After=network-online.target
+            Wants=network-online.target
+            Before=ssh.service ssh.socket
+
+            [Service]
+            # Host keys are usually generated by cloud-init
+            ExecStartPre=/usr/bin/ssh-keygen -A
+            ExecStart=/bin/bash /var/lib/fleeting/user-data
+            StandardOutput=journal+console
+            StandardError=journal+console
+        "},
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_free_link_networks() {
+        let taken = ["10.213.1.5/30", "10.213.2.9", "10.0.0.0/8", "default", "192.168.1.0/24"]
+            .into_iter()
+            .filter_map(parse_network)
+            .collect::<Vec<_>>();
+        assert!(!is_free(Ipv4Addr::new(10, 213, 1, 4), &taken));
+        assert!(!is_free(Ipv4Addr::new(10, 213, 2, 8), &taken));
+        assert!(is_free(Ipv4Addr::new(10, 213, 1, 8), &taken));
+        assert!(is_free(Ipv4Addr::new(10, 213, 2, 12), &taken));
+        assert!(!is_free(Ipv4Addr::new(10, 213, 7, 0), &[(Ipv4Addr::new(10, 213, 0, 0), 16)]));
+    }
+}
@@ -0,0 +1,682 @@
+use super::{instance_name, DestroyGuard, SpawnedVm, VmProvider};
+use crate::{steps, ubuntu_release::UbuntuRelease};
+use anyhow::Context as _;
+use async_trait::async_trait;
+use base64::prelude::*;
+use clap::Args;
+use reqwest::{Method, StatusCode, Url};
+use rsa::{
+    pkcs1::DecodeRsaPrivateKey as _,
+    pkcs1v15::SigningKey,
+    pkcs8::DecodePrivateKey as _,
+    signature::{SignatureEncoding as _, Signer as _},
+    RsaPrivateKey,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use sha2::{Digest as _, Sha256};
+use std::{collections::HashMap, env, fmt, fs, net::Ipv4Addr, path::PathBuf, time::SystemTime};
+use tokio::time::{sleep, Duration};
+
+const TAG: &str = "fleeting";
+const NETWORK_NAME: &str = "fleeting";
+
+/// Run first by user_data: Canonical's Ubuntu images for OCI ship iptables rules rejecting inbound traffic but SSH.
+const ALLOW_DOCKER_PORT: &str = "iptables -I INPUT -p tcp --dport 2376 -j ACCEPT";
+
+/// Oracle Cloud Infrastructure Compute
+#[derive(Args, Clone)]
+#[command(
+    override_usage = color_print::cstr! {r#"<bold>fleeting</bold> <bold>oci</bold> [OPTIONS] [COMMAND]...
+
+<bold><underline>Authentication:</underline></bold>
+  - API signing key in the OCI CLI config file (~/.oci/config, see 'oci setup config')
+  - Environment variables OCI_CLI_CONFIG_FILE and OCI_CLI_PROFILE select another file or profile
+
+<bold><underline>Limitations:</underline></bold>
+OCI instances stop, but are not deleted, when shut down. fleeting terminates
+the instance when the run ends and collects stopped instances at the beginning
+of the run, in case it could not.
+
+"#},)]
+pub struct Oci {
+    /// [default: region of the config profile]
+    #[arg(long)]
+    region: Option<String>,
+
+    /// Compartment OCID for the instances and their network, which is created if needed [default: tenancy]
+    #[arg(long)]
+    compartment: Option<String>,
+
+    /// [default: each of the region's, until one has capacity]
+    #[arg(long)]
+    availability_domain: Option<String>,
+
+    /// Shape, e.g. 'VM.Standard.A1.Flex' (arm64) or 'VM.Standard.E5.Flex' (amd64).
+    #[arg(long, default_value = "VM.Standard.A1.Flex")]
+    shape: String,
+
+    /// OCPUs, for flex shapes.
+    #[arg(long, default_value_t = 1.0)]
+    ocpus: f32,
+
+    /// Memory, in GBs, for flex shapes.
+    #[arg(long, default_value_t = 6.0)]
+    memory: f32,
+
+    /// Disk size, in GBs.
+    #[arg(long)]
+    disk: Option<usize>,
+
+    /// Release of Canonical's Ubuntu image.
+    #[arg(long, default_value = "24.04")]
+    ubuntu_release: UbuntuRelease,
+}
+
+#[async_trait]
+impl VmProvider for Oci {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
+        let step = steps::start();
+        log::info!("Loading OCI configuration...");
+        let (client, tenancy) = {
+            let path = match env::var_os("OCI_CLI_CONFIG_FILE") {
+                Some(path) => PathBuf::from(path),
+                None => dirs::home_dir().context("no home directory")?.join(".oci/config"),
+            };
+            let profile_name = env::var("OCI_CLI_PROFILE").unwrap_or("DEFAULT".to_owned());
+            let config = fs::read_to_string(&path).with_context(|| format!("reading {path:?}"))?;
+            let profile = parse_config_profile(&config, &profile_name).with_context(|| format!("reading {path:?}"))?;
+            let value = |key: &str| {
+                profile
+                    .get(key)
+                    .with_context(|| format!("{key} missing from profile {profile_name} in {path:?}"))
+            };
+
+            let key_file = value("key_file")?;
+            let key_file = match key_file.strip_prefix("~/") {
+                Some(relative) => dirs::home_dir().context("no home directory")?.join(relative),
+                None => PathBuf::from(key_file),
+            };
+            let pem = fs::read_to_string(&key_file).with_context(|| format!("reading {key_file:?}"))?;
+            let key = RsaPrivateKey::from_pkcs8_pem(&pem)
+                .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&pem))
+                .with_context(|| format!("decoding {key_file:?} (encrypted keys are not supported)"))?;
+
+            let region = match &self.region {
+                Some(region) => region.clone(),
+                None => value("region")?.clone(),
+            };
+            log::info!("Region: {region}");
+            let (tenancy, user) = (value("tenancy")?.clone(), value("user")?);
+            let client = Client {
+                http: reqwest::Client::new(),
+                region,
+                key_id: format!("{tenancy}/{user}/{}", value("fingerprint")?),
+                signing_key: SigningKey::new(key),
+            };
+
+            log::debug!("Validating credentials...");
+            #[derive(Deserialize)]
+            struct User {
+                name: String,
+            }
+            let user: User = client.identity(Method::GET, &format!("/users/{user}"), None).await?;
+            log::info!("Identity: {}", user.name);
+            (client, tenancy)
+        };
+        let compartment = self.compartment.clone().unwrap_or(tenancy.clone());
+
+        let step = step.next();
+        log::info!("Terminate stopped fleeting instances...");
+        {
+            let instances: Vec<Instance> = client
+                .core(Method::GET, &format!("/instances?compartmentId={compartment}&lifecycleState=STOPPED"), None)
+                .await?;
+            let stopped = instances
+                .iter()
+                .filter(|instance| instance.freeform_tags.contains_key(TAG) && instance.display_name.starts_with("fleeting-"))
+                .collect::<Vec<_>>();
+            for instance in &stopped {
+                client.terminate(&instance.id).await?;
+            }
+            log::info!("{} terminated", stopped.len());
+        }
+
+        let step = step.next();
+        log::info!("Looking up image and availability domains...");
+        let (image_id, availability_domains) = {
+            #[derive(Deserialize)]
+            #[serde(rename_all = "camelCase")]
+            struct Image {
+                id: String,
+                display_name: String,
+            }
+            let images: Vec<Image> = client
+                .core(
+                    Method::GET,
+                    &format!(
+                        "/images?compartmentId={compartment}&operatingSystem=Canonical%20Ubuntu&operatingSystemVersion={}&shape={}\
+                         &lifecycleState=AVAILABLE&sortBy=TIMECREATED&sortOrder=DESC&limit=1",
+                        self.ubuntu_release, self.shape
+                    ),
+                    None,
+                )
+                .await?;
+            let Some(image) = images.into_iter().next() else {
+                anyhow::bail!("no Canonical Ubuntu {} image for shape: {}", self.ubuntu_release, self.shape)
+            };
+            log::info!("{}", image.display_name);
+
+            let availability_domains = match &self.availability_domain {
+                Some(availability_domain) => vec![availability_domain.clone()],
+                None => {
+                    #[derive(Deserialize)]
+                    struct AvailabilityDomain {
+                        name: String,
+                    }
+                    let availability_domains: Vec<AvailabilityDomain> = client
+                        .identity(Method::GET, &format!("/availabilityDomains?compartmentId={tenancy}"), None)
+                        .await?;
+                    availability_domains.into_iter().map(|ad| ad.name).collect()
+                }
+            };
+            (image.id, availability_domains)
+        };
+
+        let step = step.next();
+        log::info!("Creating network if needed...");
+        let subnet_id = get_or_create_network(&client, &compartment).await?;
+
+        let step = step.next();
+        log::info!("Launching an instance...");
+        let instance_id = {
+            let name = instance_name();
+            let mut body = json!({
+                "compartmentId": compartment,
+                "displayName": name,
+                "shape": self.shape,
+                "sourceDetails": { "sourceType": "image", "imageId": image_id },
+                "createVnicDetails": { "subnetId": subnet_id, "assignPublicIp": true },
+                "metadata": { "user_data": BASE64_STANDARD.encode(allow_docker_port(user_data)) },
+                "freeformTags": { TAG: "" },
+            });
+            if self.shape.ends_with(".Flex") {
+                body["shapeConfig"] = json!({ "ocpus": self.ocpus, "memoryInGBs": self.memory });
+            }
+            if let Some(disk) = self.disk {
+                body["sourceDetails"]["bootVolumeSizeInGBs"] = json!(disk);
+            }
+
+            // Capacity, especially of free-tier Ampere A1, is often exhausted in some availability domains only
+            let mut launched = None;
+            for availability_domain in &availability_domains {
+                body["availabilityDomain"] = json!(availability_domain);
+                match client.core::<Instance>(Method::POST, "/instances", Some(body.clone())).await {
+                    Ok(instance) => {
+                        launched = Some(instance.id);
+                        break;
+                    }
+                    Err(e) if e.downcast_ref::<ApiError>().is_some_and(ApiError::is_out_of_capacity) => {
+                        log::warn!("{availability_domain}: {e:#}");
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            let instance_id = launched.with_context(|| format!("no capacity for {} in: {availability_domains:?}", self.shape))?;
+            log::info!("{name}");
+            instance_id
+        };
+
+        // From here on, the instance must be terminated when the run ends
+        let terminate = {
+            let (client, instance_id) = (client.clone(), instance_id.clone());
+            DestroyGuard::new(async move { client.terminate(&instance_id).await })
+        };
+        let started = async {
+            let step = step.next();
+            log::info!("Waiting for instance to start...");
+            loop {
+                log::debug!("Retrieving instance status...");
+                let instance: Instance = client.core(Method::GET, &format!("/instances/{instance_id}"), None).await?;
+                match instance.lifecycle_state.as_str() {
+                    "PROVISIONING" | "STARTING" => sleep(Duration::from_secs(2)).await,
+                    "RUNNING" => break,
+                    state => anyhow::bail!("instance transitioned into state: {state}"),
+                }
+            }
+
+            let public_ip: Ipv4Addr = loop {
+                #[derive(Deserialize)]
+                #[serde(rename_all = "camelCase")]
+                struct VnicAttachment {
+                    lifecycle_state: String,
+                    vnic_id: Option<String>,
+                }
+                let attachments: Vec<VnicAttachment> = client
+                    .core(
+                        Method::GET,
+                        &format!("/vnicAttachments?compartmentId={compartment}&instanceId={instance_id}"),
+                        None,
+                    )
+                    .await?;
+                let Some(vnic_id) = attachments.iter().find(|a| a.lifecycle_state == "ATTACHED").and_then(|a| a.vnic_id.as_ref()) else {
+                    log::debug!("VNIC not attached yet");
+                    sleep(Duration::from_secs(1)).await;
+                    continue;
+                };
+
+                #[derive(Deserialize)]
+                #[serde(rename_all = "camelCase")]
+                struct Vnic {
+                    public_ip: Option<Ipv4Addr>,
+                }
+                let vnic: Vnic = client.core(Method::GET, &format!("/vnics/{vnic_id}"), None).await?;
+                break vnic.public_ip.context("instance has no public IP")?;
+            };
+
+            steps::end(step);
+            anyhow::Ok(public_ip)
+        }
+        .await;
+
+        match started {
+            Ok(public_ip) => Ok(SpawnedVm { address: public_ip.into(), destroy: Some(terminate.disarm()), interruption_notice_command: None }),
+            Err(e) => {
+                log::info!("Terminating instance after failed start...");
+                if let Err(e) = terminate.disarm().await {
+                    log::error!("Failed to terminate instance: {e:#}");
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+/// VCN with an internet gateway and a subnet open to inbound traffic, returning the subnet's OCID.
+async fn get_or_create_network(client: &Client, compartment: &str) -> anyhow::Result<String> {
+    let log_resource = |kind: &str, (resource, created): &(Resource, bool)| {
+        log::info!("{kind} {} ({})", resource.id, if *created { "created" } else { "already existed" });
+    };
+
+    let vcn = get_or_create(
+        client,
+        &format!("/vcns?compartmentId={compartment}"),
+        json!({ "compartmentId": compartment, "displayName": NETWORK_NAME, "cidrBlocks": ["10.0.0.0/16"] }),
+    )
+    .await?;
+    log_resource("VCN", &vcn);
+    let (vcn, _) = vcn;
+
+    let internet_gateway = get_or_create(
+        client,
+        &format!("/internetGateways?compartmentId={compartment}&vcnId={}", vcn.id),
+        json!({ "compartmentId": compartment, "vcnId": vcn.id, "displayName": NETWORK_NAME, "isEnabled": true }),
+    )
+    .await?;
+    log_resource("Internet gateway", &internet_gateway);
+    let (internet_gateway, _) = internet_gateway;
+
+    let route_table_id = vcn.default_route_table_id.context("VCN has no default route table")?;
+    {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct RouteTable {
+            route_rules: Vec<serde_json::Value>,
+        }
+        let mut route_table: RouteTable = client.core(Method::GET, &format!("/routeTables/{route_table_id}"), None).await?;
+        if !route_table
+            .route_rules
+            .iter()
+            .any(|rule| rule["networkEntityId"] == internet_gateway.id.as_str())
+        {
+            route_table.route_rules.push(json!({
+                "destination": "0.0.0.0/0",
+                "destinationType": "CIDR_BLOCK",
+                "networkEntityId": internet_gateway.id,
+            }));
+            client
+                .core::<serde_json::Value>(
+                    Method::PUT,
+                    &format!("/routeTables/{route_table_id}"),
+                    Some(json!({ "routeRules": route_table.route_rules })),
+                )
+                .await?;
+            log::info!("Route to internet gateway (created)");
+        }
+    }
+
+    let security_list = get_or_create(
+        client,
+        &format!("/securityLists?compartmentId={compartment}&vcnId={}", vcn.id),
+        json!({
+            "compartmentId": compartment,
+            "vcnId": vcn.id,
+            "displayName": NETWORK_NAME,
+            "ingressSecurityRules": [{ "protocol": "all", "source": "0.0.0.0/0", "sourceType": "CIDR_BLOCK" }],
+            "egressSecurityRules": [{ "protocol": "all", "destination": "0.0.0.0/0", "destinationType": "CIDR_BLOCK" }],
+        }),
+    )
+    .await?;
+    log_resource("Security list", &security_list);
+    let (security_list, _) = security_list;
+
+    let subnet = get_or_create(
+        client,
+        &format!("/subnets?compartmentId={compartment}&vcnId={}", vcn.id),
+        json!({
+            "compartmentId": compartment,
+            "vcnId": vcn.id,
+            "displayName": NETWORK_NAME,
+            "cidrBlock": "10.0.0.0/24",
+            "routeTableId": route_table_id,
+            "securityListIds": [security_list.id],
+        }),
+    )
+    .await?;
+    log_resource("Subnet", &subnet);
+    let (subnet, _) = subnet;
+
+    Ok(subnet.id)
+}
+
+/// Looks up the resource named `NETWORK_NAME` in the `list_path` collection, creating it from `body` if missing.
+/// Waits for it to become available either way.
+async fn get_or_create(client: &Client, list_path: &str, body: serde_json::Value) -> anyhow::Result<(Resource, bool)> {
+    let collection = list_path.split('?').next().expect("path");
+    let resources: Vec<Resource> = client.core(Method::GET, &format!("{list_path}&displayName={NETWORK_NAME}"), None).await?;
+    let resources = resources
+        .into_iter()
+        .filter(|resource| !matches!(resource.lifecycle_state.as_str(), "TERMINATING" | "TERMINATED"))
+        .collect::<Vec<_>>();
+    let (mut resource, created) = match <[Resource; 1]>::try_from(resources) {
+        Ok([resource]) => (resource, false),
+        Err(resources) if resources.is_empty() => (client.core(Method::POST, collection, Some(body)).await?, true),
+        Err(resources) => anyhow::bail!("{} matching {collection}", resources.len()),
+    };
+
+    while resource.lifecycle_state != "AVAILABLE" {
+        if resource.lifecycle_state != "PROVISIONING" {
+            anyhow::bail!("{} is {}", resource.id, resource.lifecycle_state);
+        }
+        sleep(Duration::from_secs(1)).await;
+        resource = client.core(Method::GET, &format!("{collection}/{}", resource.id), None).await?;
+    }
+    Ok((resource, created))
+}
+
+/// The fields of networking resources that `get_or_create` needs.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Resource {
+    id: String,
+    lifecycle_state: String,
+    /// VCNs only.
+    default_route_table_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Instance {
+    id: String,
+    display_name: String,
+    lifecycle_state: String,
+    #[serde(default)]
+    freeform_tags: HashMap<String, String>,
+}
+
+/// The profile's settings in an OCI CLI config file, falling back to the DEFAULT profile's.
+fn parse_config_profile(config: &str, profile: &str) -> anyhow::Result<HashMap<String, String>> {
+    let mut sections = HashMap::<&str, HashMap<String, String>>::new();
+    let mut section = None;
+    for line in config.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            section = Some(name);
+        } else if let (Some(section), Some((key, value))) = (section, line.split_once('=')) {
+            sections.entry(section).or_default().insert(key.trim().to_owned(), value.trim().to_owned());
+        }
+    }
+    if !sections.contains_key(profile) {
+        anyhow::bail!("profile {profile} not found");
+    }
+    let mut settings = sections.remove("DEFAULT").unwrap_or_default();
+    settings.extend(sections.remove(profile).unwrap_or_default());
+    Ok(settings)
+}
+
+#[derive(Clone)]
+struct Client {
+    http: reqwest::Client,
+    region: String,
+    key_id: String,
+    signing_key: SigningKey<Sha256>,
+}
+
+impl Client {
+    /// Headers to sign, and the `authorization` header signing them, as specified in
+    /// https://docs.oracle.com/en-us/iaas/Content/API/Concepts/signingrequests.htm
+    fn sign(&self, method: &Method, url: &Url, date: &str, body: Option<&str>) -> (Vec<(&'static str, String)>, String) {
+        let request_target = match url.query() {
+            Some(query) => format!("{} {}?{query}", method.as_str().to_lowercase(), url.path()),
+            None => format!("{} {}", method.as_str().to_lowercase(), url.path()),
+        };
+        let mut headers = vec![
+            ("date", date.to_owned()),
+            ("(request-target)", request_target),
+            ("host", url.host_str().expect("host").to_owned()),
+        ];
+        if let Some(body) = body {
+            headers.push(("content-length", body.len().to_string()));
+            headers.push(("content-type", "application/json".to_owned()));
+            headers.push(("x-content-sha256", BASE64_STANDARD.encode(Sha256::digest(body))));
+        }
+        let signature = BASE64_STANDARD.encode(self.signing_key.sign(signing_string(&headers).as_bytes()).to_bytes());
+        let authorization = format!(
+            r#"Signature version="1",keyId="{}",algorithm="rsa-sha256",headers="{}",signature="{signature}""#,
+            self.key_id,
+            headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(" "),
+        );
+        (headers, authorization)
+    }
+
+    /// Core Services API (compute, networking).
+    async fn core<T: DeserializeOwned>(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> anyhow::Result<T> {
+        let url = format!("https://iaas.{}.oraclecloud.com/20160918{path}", self.region);
+        Ok(self.send(method, &url, body).await?.json().await?)
+    }
+
+    /// Identity and Access Management API.
+    async fn identity<T: DeserializeOwned>(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> anyhow::Result<T> {
+        let url = format!("https://identity.{}.oci.oraclecloud.com/20160918{path}", self.region);
+        Ok(self.send(method, &url, body).await?.json().await?)
+    }
+
+    /// Terminates the instance along with its boot volume.
+    async fn terminate(&self, instance_id: &str) -> anyhow::Result<()> {
+        let url = format!(
+            "https://iaas.{}.oraclecloud.com/20160918/instances/{instance_id}?preserveBootVolume=false",
+            self.region
+        );
+        self.send(Method::DELETE, &url, None).await?;
+        Ok(())
+    }
+
+    /// Sends a request signed with the API key.
+    async fn send(&self, method: Method, url: &str, body: Option<serde_json::Value>) -> anyhow::Result<reqwest::Response> {
+        log::debug!("{method} {url}");
+        let url = Url::parse(url)?;
+        let body = body.map(|body| body.to_string());
+        let (headers, authorization) = self.sign(&method, &url, &httpdate::fmt_http_date(SystemTime::now()), body.as_deref());
+
+        let mut request = self.http.request(method.clone(), url.clone()).header("authorization", authorization);
+        for (name, value) in headers {
+            // reqwest sets these itself
+            if !matches!(name, "(request-target)" | "host" | "content-length") {
+                request = request.header(name, value);
+            }
+        }
+        if let Some(body) = body {
+            request = request.body(body);
+        }
+        let response = request.send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            match response.json::<ApiError>().await {
+                Ok(error) => return Err(anyhow::Error::new(ApiError { status, ..error }).context(format!("{method} {} failed", url.path()))),
+                Err(_) => anyhow::bail!("{method} {} failed: {status}", url.path()),
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// `user_data` with `ALLOW_DOCKER_PORT` inserted after its shebang.
+fn allow_docker_port(user_data: &str) -> String {
+    match user_data.split_once('\n') {
+        Some((shebang, script)) if shebang.starts_with("#!") => format!("{shebang}\n{ALLOW_DOCKER_PORT}\n{script}"),
+        _ => format!("{ALLOW_DOCKER_PORT}\n{user_data}"),
+    }
+}
+
+fn signing_string(headers: &[(&str, String)]) -> String {
+    headers.iter().map(|(name, value)| format!("{name}: {value}")).collect::<Vec<_>>().join("\n")
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    #[serde(skip, default)]
+    status: StatusCode,
+    code: String,
+    message: String,
+}
+
+impl ApiError {
+    fn is_out_of_capacity(&self) -> bool {
+        self.status == StatusCode::INTERNAL_SERVER_ERROR && self.message.contains("Out of host capacity")
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng as _};
+    use rsa::{
+        pkcs1v15::{Signature, VerifyingKey},
+        signature::Verifier as _,
+    };
+
+    // Requests and key ID of the examples in https://docs.oracle.com/en-us/iaas/Content/API/Concepts/signingrequests.htm
+    const KEY_ID: &str = "ocid1.tenancy.oc1..aaaaaaaaba3pv6wkcr4jqae5f15p2b2m2yt2j6rx32uzr4h25vqstifsfdsq/ocid1.user.oc1..aaaaaaaat5nvwcna5j6aqzjcaty5eqbb6qt2jvpkanghtgdaqedqw3rynjq/20:3b:97:13:55:1c:5b:0d:d3:37:d8:50:4e:c5:3a:34";
+    const DATE: &str = "Thu, 05 Jan 2014 21:31:40 GMT";
+
+    fn client(key: &RsaPrivateKey) -> Client {
+        Client {
+            http: reqwest::Client::new(),
+            region: "us-phoenix-1".to_owned(),
+            key_id: KEY_ID.to_owned(),
+            signing_key: SigningKey::new(key.clone()),
+        }
+    }
+
+    /// Checks the authorization header names the headers in order and its signature verifies against the signing string.
+    fn assert_signed(key: &RsaPrivateKey, headers: &[(&str, String)], authorization: &str, header_names: &str) {
+        let (prefix, signature) = authorization.split_once(r#",signature=""#).unwrap();
+        assert_eq!(
+            prefix,
+            format!(r#"Signature version="1",keyId="{KEY_ID}",algorithm="rsa-sha256",headers="{header_names}""#)
+        );
+        let signature = Signature::try_from(BASE64_STANDARD.decode(signature.strip_suffix('"').unwrap()).unwrap().as_slice()).unwrap();
+        let verifying_key = VerifyingKey::<Sha256>::new(key.to_public_key());
+        verifying_key.verify(signing_string(headers).as_bytes(), &signature).unwrap();
+    }
+
+    #[test]
+    fn signs_get_request() {
+        // The documented key isn't reproduced here, so signatures are verified rather than compared
+        let key = RsaPrivateKey::new(&mut StdRng::seed_from_u64(0), 1024).unwrap();
+        let url = Url::parse("https://iaas.us-phoenix-1.oraclecloud.com/20160918/instances?availabilityDomain=Pjwf%3A%20PHX-AD-1&compartmentId=ocid1.compartment.oc1..aaaaaaaam3we6vgnherjq5q2idnccdflvjsnog7mlr6rtdb25gilchfeyjxa&displayName=TeamXInstances&volumeId=ocid1.volume.oc1.phx.abyhqljrgvttnlx73nmrwfaux7kcvzfs3s66izvxf2h4lgvyndsdsnoiwr5q").unwrap();
+
+        let (headers, authorization) = client(&key).sign(&Method::GET, &url, DATE, None);
+
+        assert_eq!(
+            signing_string(&headers),
+            "date: Thu, 05 Jan 2014 21:31:40 GMT\n\
+             (request-target): get /20160918/instances?availabilityDomain=Pjwf%3A%20PHX-AD-1&compartmentId=ocid1.compartment.oc1..aaaaaaaam3we6vgnherjq5q2idnccdflvjsnog7mlr6rtdb25gilchfeyjxa&displayName=TeamXInstances&volumeId=ocid1.volume.oc1.phx.abyhqljrgvttnlx73nmrwfaux7kcvzfs3s66izvxf2h4lgvyndsdsnoiwr5q\n\
+             host: iaas.us-phoenix-1.oraclecloud.com"
+        );
+        assert_signed(&key, &headers, &authorization, "date (request-target) host");
+    }
+
+    #[test]
+    fn signs_post_request() {
+        let key = RsaPrivateKey::new(&mut StdRng::seed_from_u64(0), 1024).unwrap();
+        let url = Url::parse("https://iaas.us-phoenix-1.oraclecloud.com/20160918/volumeAttachments").unwrap();
+        let body = r#"{
+    "compartmentId": "ocid1.compartment.oc1..aaaaaaaam3we6vgnherjq5q2idnccdflvjsnog7mlr6rtdb25gilchfeyjxa",
+    "instanceId": "ocid1.instance.oc1.phx.abuw4ljrlsfiqw6vzzxb43vyypt4pkodawglp3wqxjqofakrwvou52gb6s5a",
+    "volumeId": "ocid1.volume.oc1.phx.abyhqljrgvttnlx73nmrwfaux7kcvzfs3s66izvxf2h4lgvyndsdsnoiwr5q"
+}"#;
+
+        let (headers, authorization) = client(&key).sign(&Method::POST, &url, DATE, Some(body));
+
+        assert_eq!(
+            signing_string(&headers),
+            "date: Thu, 05 Jan 2014 21:31:40 GMT\n\
+             (request-target): post /20160918/volumeAttachments\n\
+             host: iaas.us-phoenix-1.oraclecloud.com\n\
+             content-length: 316\n\
+             content-type: application/json\n\
+             x-content-sha256: V9Z20UJTvkvpJ50flBzKE32+6m2zJjweHpDMX/U4Uy0="
+        );
+        assert_signed(
+            &key,
+            &headers,
+            &authorization,
+            "date (request-target) host content-length content-type x-content-sha256",
+        );
+    }
+
+    #[test]
+    fn parses_config_profile() {
+        let config = "\
+# comment
+[DEFAULT]
+user = ocid1.user.oc1..default
+region=us-ashburn-1
+
+; other comment
+[OTHER]
+user=ocid1.user.oc1..other
+key_file = ~/.oci/other.pem
+";
+        let profile = parse_config_profile(config, "OTHER").unwrap();
+        assert_eq!(profile["user"], "ocid1.user.oc1..other");
+        assert_eq!(profile["key_file"], "~/.oci/other.pem");
+        assert_eq!(profile["region"], "us-ashburn-1");
+
+        let profile = parse_config_profile(config, "DEFAULT").unwrap();
+        assert_eq!(profile["user"], "ocid1.user.oc1..default");
+        assert!(!profile.contains_key("key_file"));
+
+        assert!(parse_config_profile(config, "MISSING").is_err());
+    }
+
+    #[test]
+    fn allows_docker_port_after_shebang() {
+        assert_eq!(
+            allow_docker_port("#!/bin/bash\necho hi\n"),
+            format!("#!/bin/bash\n{ALLOW_DOCKER_PORT}\necho hi\n")
+        );
+    }
+}
@@ -0,0 +1,667 @@
+use super::{instance_name, DestroyGuard, SpawnedVm, VmProvider};
+use crate::steps;
+use anyhow::Context as _;
+use async_trait::async_trait;
+use base64::prelude::*;
+use clap::{Args, ValueEnum};
+use futures::FutureExt as _;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::Method;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use std::{collections::HashMap, env, fs, net::Ipv4Addr, path::PathBuf};
+use tokio::time::{sleep, Duration};
+
+const TAG: &str = "fleeting";
+const SECURITY_GROUP_NAME: &str = "fleeting";
+
+/// OpenStack Compute (Nova)
+#[derive(Args, Clone)]
+#[command(
+    override_usage = color_print::cstr! {r#"<bold>fleeting</bold> <bold>openstack</bold> [OPTIONS] [COMMAND]...
+
+<bold><underline>Authentication:</underline></bold>
+  - clouds.yaml (./, ~/.config/openstack/, /etc/openstack/), selected with '--cloud' or OS_CLOUD
+  - Environment variables (OS_AUTH_URL, OS_USERNAME, OS_PASSWORD, OS_PROJECT_NAME or OS_PROJECT_ID, ...)
+  - Application credentials, in either of the above
+Only Keystone v3 password and application credential authentication is
+supported.
+
+<bold><underline>Limitations:</underline></bold>
+Depending on the cloud, servers may stop, but not be deleted, when shut down.
+fleeting deletes the server and its floating IP when the run ends and collects
+stopped servers and unused floating IPs at the beginning of the run, in case
+it could not.
+
+"#},)]
+pub struct OpenStack {
+    /// Cloud in clouds.yaml [default: $OS_CLOUD, else OS_* environment variables are used]
+    #[arg(long)]
+    cloud: Option<String>,
+
+    /// Image name or ID, e.g. an Ubuntu 24.04 cloud image [required]
+    #[arg(long)]
+    image: String,
+
+    /// Flavor name or ID [required]
+    #[arg(long)]
+    flavor: String,
+
+    /// Network name or ID to attach the server to [default: chosen by Nova, if the project has a single one]
+    #[arg(long)]
+    network: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = FloatingIp::Auto)]
+    floating_ip: FloatingIp,
+
+    /// External network name or ID to allocate floating IPs from [default: the only external network]
+    #[arg(long)]
+    floating_network: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum FloatingIp {
+    /// Attach one when the server's fixed IPv4 address is private
+    Auto,
+    Always,
+    Never,
+}
+
+#[async_trait]
+impl VmProvider for OpenStack {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
+        let step = steps::start();
+        log::info!("Authenticating with Keystone...");
+        let client = {
+            let credentials = match self.cloud.clone().or(env::var("OS_CLOUD").ok()) {
+                Some(cloud) => Credentials::from_clouds_yaml(&cloud)?,
+                None => Credentials::from_env()?,
+            };
+            let client = Client::authenticate(&credentials).await?;
+            log::info!("Project: {}", client.project);
+            client
+        };
+
+        let step = step.next();
+        log::info!("Delete stopped fleeting servers...");
+        {
+            let servers: Servers = client
+                .request(Method::GET, &client.compute("/servers/detail?status=SHUTOFF&name=^fleeting-"), None)
+                .await?;
+            let stopped = servers
+                .servers
+                .iter()
+                .filter(|server| server.name.starts_with("fleeting-") && server.metadata.contains_key(TAG))
+                .collect::<Vec<_>>();
+            for server in &stopped {
+                client.delete(&client.compute(&format!("/servers/{}", server.id))).await?;
+            }
+            log::info!("{} deleted", stopped.len());
+
+            let floating_ips: FloatingIps = client
+                .request(Method::GET, &client.network(&format!("/v2.0/floatingips?description={TAG}")), None)
+                .await?;
+            let unused = floating_ips
+                .floatingips
+                .iter()
+                .filter(|floating_ip| floating_ip.port_id.is_none())
+                .collect::<Vec<_>>();
+            for floating_ip in &unused {
+                client.delete(&client.network(&format!("/v2.0/floatingips/{}", floating_ip.id))).await?;
+            }
+            log::info!("{} unused floating IPs released", unused.len());
+        }
+
+        let step = step.next();
+        log::info!("Looking up image and flavor...");
+        let (image_id, flavor_id) = {
+            #[derive(Deserialize)]
+            struct Images {
+                images: Vec<Named>,
+            }
+            let images: Images = client
+                .request(
+                    Method::GET,
+                    &client.image(&format!("/v2/images?name={}", utf8_percent_encode(&self.image, NON_ALPHANUMERIC))),
+                    None,
+                )
+                .await?;
+            let image = match &*images.images {
+                [] => client
+                    .request(Method::GET, &client.image(&format!("/v2/images/{}", self.image)), None)
+                    .await
+                    .with_context(|| format!("image not found: {}", self.image))?,
+                _ => find_by_name_or_id(images.images, &self.image, "image")?,
+            };
+            log::info!("Image: {} ({})", image.name, image.id);
+
+            #[derive(Deserialize)]
+            struct Flavors {
+                flavors: Vec<Named>,
+            }
+            let flavors: Flavors = client.request(Method::GET, &client.compute("/flavors"), None).await?;
+            let flavor = find_by_name_or_id(flavors.flavors, &self.flavor, "flavor")?;
+            log::info!("Flavor: {} ({})", flavor.name, flavor.id);
+            (image.id, flavor.id)
+        };
+
+        let step = step.next();
+        log::info!("Creating security group if needed...");
+        {
+            let (id, created) = get_or_create_security_group(&client).await?;
+            if created {
+                log::info!("{id} (created)");
+            } else {
+                log::info!("{id} (already existed)");
+            }
+        }
+
+        let step = step.next();
+        log::info!("Launching a server...");
+        let server_id = {
+            let name = instance_name();
+            let mut server = json!({
+                "name": name,
+                "imageRef": image_id,
+                "flavorRef": flavor_id,
+                "user_data": BASE64_STANDARD.encode(user_data),
+                "security_groups": [{ "name": SECURITY_GROUP_NAME }],
+                "metadata": { TAG: "true" },
+            });
+            if let Some(network) = &self.network {
+                #[derive(Deserialize)]
+                struct Networks {
+                    networks: Vec<Named>,
+                }
+                let networks: Networks = client.request(Method::GET, &client.network("/v2.0/networks"), None).await?;
+                server["networks"] = json!([{ "uuid": find_by_name_or_id(networks.networks, network, "network")?.id }]);
+            }
+
+            #[derive(Deserialize)]
+            struct Created {
+                server: CreatedServer,
+            }
+            #[derive(Deserialize)]
+            struct CreatedServer {
+                id: String,
+            }
+            let created: Created = client
+                .request(Method::POST, &client.compute("/servers"), Some(json!({ "server": server })))
+                .await?;
+            log::info!("{name}");
+            created.server.id
+        };
+
+        // From here on, the server must be deleted when the run ends
+        let delete_server = {
+            let (client, url) = (client.clone(), client.compute(&format!("/servers/{server_id}")));
+            DestroyGuard::new(async move { client.delete(&url).await })
+        };
+        let started = async {
+            let step = step.next();
+            log::info!("Waiting for server to start...");
+            let fixed_ip = loop {
+                log::debug!("Retrieving server status...");
+                #[derive(Deserialize)]
+                struct GetServer {
+                    server: Server,
+                }
+                let GetServer { server } = client.request(Method::GET, &client.compute(&format!("/servers/{server_id}")), None).await?;
+                match server.status.as_str() {
+                    "BUILD" => sleep(Duration::from_secs(2)).await,
+                    "ACTIVE" => {
+                        break server
+                            .addresses
+                            .values()
+                            .flatten()
+                            .find(|address| address.version == 4)
+                            .and_then(|address| address.addr.parse::<Ipv4Addr>().ok())
+                            .context("server has no IPv4 address")?
+                    }
+                    "ERROR" => anyhow::bail!("server failed to build: {}", server.fault.map(|fault| fault.message).unwrap_or_default()),
+                    state => anyhow::bail!("server transitioned into state: {state}"),
+                }
+            };
+            log::info!("{fixed_ip}");
+
+            let step = step.next();
+            log::info!("Attaching a floating IP if needed...");
+            let attach = match self.floating_ip {
+                FloatingIp::Auto => fixed_ip.is_private(),
+                FloatingIp::Always => true,
+                FloatingIp::Never => false,
+            };
+            if !attach {
+                log::info!("Not needed");
+                steps::end(step);
+                return anyhow::Ok((fixed_ip, None));
+            }
+            let floating_network_id = {
+                #[derive(Deserialize)]
+                struct Networks {
+                    networks: Vec<Named>,
+                }
+                let networks: Networks = client
+                    .request(Method::GET, &client.network("/v2.0/networks?router:external=true"), None)
+                    .await?;
+                match (&self.floating_network, &*networks.networks) {
+                    (Some(floating_network), _) => find_by_name_or_id(networks.networks, floating_network, "external network")?.id,
+                    (None, [network]) => network.id.clone(),
+                    (None, networks) => anyhow::bail!("{} external networks, choose one with '--floating-network'", networks.len()),
+                }
+            };
+            let port_id = {
+                #[derive(Deserialize)]
+                struct Ports {
+                    ports: Vec<Port>,
+                }
+                #[derive(Deserialize)]
+                struct Port {
+                    id: String,
+                }
+                let ports: Ports = client
+                    .request(Method::GET, &client.network(&format!("/v2.0/ports?device_id={server_id}")), None)
+                    .await?;
+                ports.ports.into_iter().next().context("server has no port")?.id
+            };
+            // Associated on creation, so it cannot be left allocated but unused
+            let floating_ip = {
+                #[derive(Deserialize)]
+                struct Created {
+                    floatingip: FloatingIpAddress,
+                }
+                let created: Created = client
+                    .request(
+                        Method::POST,
+                        &client.network("/v2.0/floatingips"),
+                        Some(json!({
+                            "floatingip": { "floating_network_id": floating_network_id, "port_id": port_id, "description": TAG },
+                        })),
+                    )
+                    .await?;
+                created.floatingip
+            };
+            log::info!("{}", floating_ip.floating_ip_address);
+
+            steps::end(step);
+            anyhow::Ok((floating_ip.floating_ip_address, Some(floating_ip.id)))
+        }
+        .await;
+
+        match started {
+            Ok((ip, floating_ip_id)) => {
+                let release = floating_ip_id.map(|id| {
+                    let (client, url) = (client.clone(), client.network(&format!("/v2.0/floatingips/{id}")));
+                    async move { client.delete(&url).await }
+                });
+                let delete_server = delete_server.disarm();
+                let destroy = async move {
+                    if let Some(release) = release {
+                        release.await?;
+                    }
+                    delete_server.await
+                };
+                Ok(SpawnedVm { address: ip.into(), destroy: Some(destroy.boxed()), interruption_notice_command: None })
+            }
+            Err(e) => {
+                log::info!("Deleting server after failed start...");
+                if let Err(e) = delete_server.disarm().await {
+                    log::error!("Failed to delete server: {e:#}");
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+async fn get_or_create_security_group(client: &Client) -> anyhow::Result<(String, bool)> {
+    #[derive(Deserialize)]
+    struct SecurityGroups {
+        security_groups: Vec<Named>,
+    }
+    let security_groups: SecurityGroups = client
+        .request(
+            Method::GET,
+            &client.network(&format!("/v2.0/security-groups?name={SECURITY_GROUP_NAME}&project_id={}", client.project_id)),
+            None,
+        )
+        .await?;
+    match &*security_groups.security_groups {
+        [] => {
+            #[derive(Deserialize)]
+            struct Created {
+                security_group: Named,
+            }
+            let created: Created = client
+                .request(
+                    Method::POST,
+                    &client.network("/v2.0/security-groups"),
+                    Some(json!({ "security_group": { "name": SECURITY_GROUP_NAME, "description": "fleeting ephemeral instances" } })),
+                )
+                .await?;
+            let group_id = created.security_group.id;
+            for ethertype in ["IPv4", "IPv6"] {
+                client
+                    .request::<serde_json::Value>(
+                        Method::POST,
+                        &client.network("/v2.0/security-group-rules"),
+                        Some(json!({ "security_group_rule": { "security_group_id": group_id, "direction": "ingress", "ethertype": ethertype } })),
+                    )
+                    .await?;
+            }
+            Ok((group_id, true))
+        }
+        [sg] => Ok((sg.id.clone(), false)),
+        x => anyhow::bail!("{} matching security groups", x.len()),
+    }
+}
+
+/// Any resource with a name and an ID.
+#[derive(Deserialize)]
+struct Named {
+    id: String,
+    #[serde(default)]
+    name: String,
+}
+
+fn find_by_name_or_id(resources: Vec<Named>, name_or_id: &str, kind: &str) -> anyhow::Result<Named> {
+    let mut matching = resources
+        .into_iter()
+        .filter(|resource| resource.id == name_or_id || resource.name == name_or_id)
+        .collect::<Vec<_>>();
+    match matching.len() {
+        1 => Ok(matching.remove(0)),
+        0 => anyhow::bail!("{kind} not found: {name_or_id}"),
+        n => anyhow::bail!("{n} matching {kind}s: {name_or_id}"),
+    }
+}
+
+#[derive(Deserialize)]
+struct Servers {
+    servers: Vec<Server>,
+}
+
+#[derive(Deserialize)]
+struct Server {
+    id: String,
+    name: String,
+    status: String,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+    #[serde(default)]
+    addresses: HashMap<String, Vec<Address>>,
+    fault: Option<Fault>,
+}
+
+#[derive(Deserialize)]
+struct Address {
+    addr: String,
+    version: u8,
+}
+
+#[derive(Deserialize)]
+struct Fault {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct FloatingIps {
+    floatingips: Vec<FloatingIpAddress>,
+}
+
+#[derive(Deserialize)]
+struct FloatingIpAddress {
+    id: String,
+    floating_ip_address: Ipv4Addr,
+    port_id: Option<String>,
+}
+
+/// What is needed to get a token and pick endpoints, from clouds.yaml or OS_* variables.
+struct Credentials {
+    auth_url: String,
+    username: Option<String>,
+    password: Option<String>,
+    user_domain_name: Option<String>,
+    project_name: Option<String>,
+    project_id: Option<String>,
+    project_domain_name: Option<String>,
+    application_credential_id: Option<String>,
+    application_credential_secret: Option<String>,
+    region_name: Option<String>,
+    interface: Option<String>,
+}
+
+impl Credentials {
+    fn from_env() -> anyhow::Result<Self> {
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+        Ok(Self {
+            auth_url: var("OS_AUTH_URL").context("neither '--cloud', OS_CLOUD nor OS_AUTH_URL set")?,
+            username: var("OS_USERNAME"),
+            password: var("OS_PASSWORD"),
+            user_domain_name: var("OS_USER_DOMAIN_NAME"),
+            project_name: var("OS_PROJECT_NAME").or(var("OS_TENANT_NAME")),
+            project_id: var("OS_PROJECT_ID").or(var("OS_TENANT_ID")),
+            project_domain_name: var("OS_PROJECT_DOMAIN_NAME"),
+            application_credential_id: var("OS_APPLICATION_CREDENTIAL_ID"),
+            application_credential_secret: var("OS_APPLICATION_CREDENTIAL_SECRET"),
+            region_name: var("OS_REGION_NAME"),
+            interface: var("OS_INTERFACE"),
+        })
+    }
+
+    fn from_clouds_yaml(cloud: &str) -> anyhow::Result<Self> {
+        let mut candidates = vec![PathBuf::from("clouds.yaml")];
+        if let Some(config_dir) = dirs::home_dir() {
+            candidates.push(config_dir.join(".config/openstack/clouds.yaml"));
+        }
+        candidates.push(PathBuf::from("/etc/openstack/clouds.yaml"));
+        let path = candidates
+            .iter()
+            .find(|path| path.exists())
+            .with_context(|| format!("no clouds.yaml found in: {candidates:?}"))?;
+
+        #[derive(Deserialize)]
+        struct CloudsYaml {
+            clouds: HashMap<String, Cloud>,
+        }
+        #[derive(Deserialize)]
+        struct Cloud {
+            auth: Auth,
+            region_name: Option<String>,
+            interface: Option<String>,
+        }
+        #[derive(Deserialize)]
+        struct Auth {
+            auth_url: Option<String>,
+            username: Option<String>,
+            password: Option<String>,
+            user_domain_name: Option<String>,
+            project_name: Option<String>,
+            project_id: Option<String>,
+            project_domain_name: Option<String>,
+            application_credential_id: Option<String>,
+            application_credential_secret: Option<String>,
+        }
+        let text = fs::read_to_string(path).with_context(|| format!("reading {path:?}"))?;
+        let mut clouds: CloudsYaml = serde_norway::from_str(&text).with_context(|| format!("parsing {path:?}"))?;
+        let Cloud { auth, region_name, interface } = clouds.clouds.remove(cloud).with_context(|| format!("cloud {cloud} not found in {path:?}"))?;
+        Ok(Self {
+            auth_url: auth.auth_url.with_context(|| format!("auth.auth_url missing for cloud {cloud} in {path:?}"))?,
+            username: auth.username,
+            password: auth.password,
+            user_domain_name: auth.user_domain_name,
+            project_name: auth.project_name,
+            project_id: auth.project_id,
+            project_domain_name: auth.project_domain_name,
+            application_credential_id: auth.application_credential_id,
+            application_credential_secret: auth.application_credential_secret,
+            region_name,
+            interface,
+        })
+    }
+
+    /// Body of a Keystone v3 token request.
+    fn token_request(&self) -> anyhow::Result<serde_json::Value> {
+        if let (Some(id), Some(secret)) = (&self.application_credential_id, &self.application_credential_secret) {
+            // Application credentials are scoped to their project already
+            return Ok(json!({
+                "auth": { "identity": { "methods": ["application_credential"], "application_credential": { "id": id, "secret": secret } } }
+            }));
+        }
+
+        let (Some(username), Some(password)) = (&self.username, &self.password) else {
+            anyhow::bail!("username and password, or an application credential, required")
+        };
+        let project = match (&self.project_id, &self.project_name) {
+            (Some(id), _) => json!({ "id": id }),
+            (None, Some(name)) => json!({ "name": name, "domain": { "name": self.project_domain_name.as_deref().unwrap_or("Default") } }),
+            (None, None) => anyhow::bail!("project name or ID required"),
+        };
+        Ok(json!({
+            "auth": {
+                "identity": {
+                    "methods": ["password"],
+                    "password": {
+                        "user": {
+                            "name": username,
+                            "password": password,
+                            "domain": { "name": self.user_domain_name.as_deref().unwrap_or("Default") },
+                        }
+                    }
+                },
+                "scope": { "project": project },
+            }
+        }))
+    }
+}
+
+#[derive(Clone)]
+struct Client {
+    http: reqwest::Client,
+    token: String,
+    project: String,
+    project_id: String,
+    compute_url: String,
+    network_url: String,
+    image_url: String,
+}
+
+impl Client {
+    async fn authenticate(credentials: &Credentials) -> anyhow::Result<Self> {
+        let http = reqwest::Client::new();
+        let auth_url = credentials.auth_url.trim_end_matches('/');
+        let auth_url = if auth_url.ends_with("/v3") {
+            auth_url.to_owned()
+        } else {
+            format!("{auth_url}/v3")
+        };
+        let response = http.post(format!("{auth_url}/auth/tokens")).json(&credentials.token_request()?).send().await?;
+        if !response.status().is_success() {
+            anyhow::bail!("authentication failed: {}: {}", response.status(), response.text().await?);
+        }
+        let token = response
+            .headers()
+            .get("x-subject-token")
+            .context("no X-Subject-Token in Keystone response")?
+            .to_str()?
+            .to_owned();
+
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            token: Token,
+        }
+        #[derive(Deserialize)]
+        struct Token {
+            project: Project,
+            #[serde(default)]
+            catalog: Vec<Service>,
+        }
+        #[derive(Deserialize)]
+        struct Project {
+            id: String,
+            name: String,
+        }
+        #[derive(Deserialize)]
+        struct Service {
+            #[serde(rename = "type")]
+            service_type: String,
+            endpoints: Vec<Endpoint>,
+        }
+        #[derive(Deserialize)]
+        struct Endpoint {
+            interface: String,
+            region_id: Option<String>,
+            url: String,
+        }
+        let TokenResponse { token: body } = response.json().await?;
+
+        let interface = credentials.interface.as_deref().unwrap_or("public");
+        let endpoint = |service_type: &str| -> anyhow::Result<String> {
+            let url = body
+                .catalog
+                .iter()
+                .filter(|service| service.service_type == service_type)
+                .flat_map(|service| &service.endpoints)
+                .find(|endpoint| {
+                    endpoint.interface == interface
+                        && credentials
+                            .region_name
+                            .as_ref()
+                            .is_none_or(|region| endpoint.region_id.as_ref() == Some(region))
+                })
+                .with_context(|| format!("no {interface} {service_type} endpoint in the service catalog"))?;
+            Ok(url.url.trim_end_matches('/').to_owned())
+        };
+        Ok(Self {
+            compute_url: endpoint("compute")?,
+            network_url: endpoint("network")?,
+            image_url: endpoint("image")?,
+            http,
+            token,
+            project: body.project.name,
+            project_id: body.project.id,
+        })
+    }
+
+    fn compute(&self, path: &str) -> String {
+        format!("{}{path}", self.compute_url)
+    }
+
+    fn network(&self, path: &str) -> String {
+        format!("{}{path}", self.network_url)
+    }
+
+    fn image(&self, path: &str) -> String {
+        format!("{}{path}", self.image_url)
+    }
+
+    async fn request<T: DeserializeOwned>(&self, method: Method, url: &str, body: Option<serde_json::Value>) -> anyhow::Result<T> {
+        Ok(self.send(method, url, body).await?.json().await?)
+    }
+
+    /// For deletions, which respond with `204 No Content`.
+    async fn delete(&self, url: &str) -> anyhow::Result<()> {
+        self.send(Method::DELETE, url, None).await?;
+        Ok(())
+    }
+
+    async fn send(&self, method: Method, url: &str, body: Option<serde_json::Value>) -> anyhow::Result<reqwest::Response> {
+        log::debug!("{method} {url}");
+        let mut request = self.http.request(method.clone(), url).header("x-auth-token", &self.token);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            // Each service wraps errors differently, e.g. {"itemNotFound": {"message": ...}} or {"NeutronError": {"message": ...}}
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            let message = serde_json::from_str::<serde_json::Value>(&text)
+                .ok()
+                .and_then(|error| error.as_object()?.values().find_map(|inner| inner["message"].as_str().map(str::to_owned)))
+                .unwrap_or(text);
+            anyhow::bail!("{method} {url} failed: {status}: {message}");
+        }
+        Ok(response)
+    }
+}
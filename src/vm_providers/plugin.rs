@@ -0,0 +1,363 @@
+use super::{SpawnedVm, VmAddress, VmProvider};
+use crate::steps;
+use anyhow::Context as _;
+use async_trait::async_trait;
+use clap::{ArgAction, ArgMatches, Command};
+use futures::FutureExt as _;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::{self, Read, Write as _},
+    net::Ipv4Addr,
+    path::PathBuf,
+    process::Stdio,
+    sync::{Arc, Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
+    process,
+};
+
+const EXECUTABLE_PREFIX: &str = "fleeting-provider-";
+
+/// Provider implemented by an external `fleeting-provider-<name>` executable on PATH.
+///
+/// Each operation runs the executable once, writes a JSON request to its stdin
+/// and reads a JSON response from its stdout. Stderr is logged.
+#[derive(Clone)]
+pub struct Plugin {
+    description: Arc<Description>,
+    args: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+pub struct Description {
+    #[serde(skip)]
+    name: String,
+    #[serde(skip)]
+    executable: PathBuf,
+
+    /// Shown as the provider's heading in help.
+    about: String,
+    #[serde(default)]
+    args: Vec<ArgDescription>,
+    #[serde(default)]
+    teardown_command: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ArgDescription {
+    name: String,
+    #[serde(default)]
+    help: Option<String>,
+    #[serde(default)]
+    value_name: Option<String>,
+    #[serde(default)]
+    default: Option<String>,
+    #[serde(default)]
+    required: bool,
+    /// Takes no value, passed as `true`/`false`.
+    #[serde(default)]
+    flag: bool,
+}
+
+impl Description {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The provider's subcommand, with the args declared by the plugin.
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(self.name.clone()).about(self.about.clone());
+        for arg in &self.args {
+            let mut clap_arg = clap::Arg::new(arg.name.clone()).long(arg.name.clone());
+            if let Some(help) = &arg.help {
+                clap_arg = clap_arg.help(help.clone());
+            }
+            if arg.flag {
+                clap_arg = clap_arg.action(ArgAction::SetTrue);
+            } else {
+                clap_arg = clap_arg.required(arg.required);
+                if let Some(value_name) = &arg.value_name {
+                    clap_arg = clap_arg.value_name(value_name.clone());
+                }
+                if let Some(default) = &arg.default {
+                    clap_arg = clap_arg.default_value(default.clone());
+                }
+            }
+            command = command.arg(clap_arg);
+        }
+        command
+    }
+}
+
+/// How long a plugin may take to describe itself, since that blocks parsing the command line.
+const DESCRIBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Plugins on PATH, except those named like a built-in provider.
+///
+/// Runs at most once, since clap may build the command more than once.
+/// Plugins that fail to describe themselves are skipped, see `warn_skipped`.
+pub fn discover(is_built_in: impl Fn(&str) -> bool) -> &'static [Arc<Description>] {
+    static DISCOVERED: OnceLock<Vec<Arc<Description>>> = OnceLock::new();
+    DISCOVERED.get_or_init(|| {
+        find_executables()
+            .into_iter()
+            .filter(|(name, _)| !is_built_in(name))
+            .filter_map(|(name, executable)| match describe(&name, executable) {
+                Ok(description) => Some(Arc::new(description)),
+                Err(e) => {
+                    SKIPPED.lock().expect("not poisoned").push(format!("skipping plugin {name:?}: {e:#}"));
+                    None
+                }
+            })
+            .collect()
+    })
+}
+
+/// Warnings about skipped plugins, kept until logging is set up after parsing the command line.
+static SKIPPED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Logs why plugins were skipped, once.
+pub fn warn_skipped() {
+    for warning in std::mem::take(&mut *SKIPPED.lock().expect("not poisoned")) {
+        log::warn!("{warning}");
+    }
+}
+
+/// `fleeting-provider-<name>` executables by name, the first one on PATH winning.
+fn find_executables() -> BTreeMap<String, PathBuf> {
+    let mut executables = BTreeMap::new();
+    for dir in env::split_paths(&env::var_os("PATH").unwrap_or_default()) {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let Some(name) = file_name
+                .to_str()
+                .and_then(|file_name| file_name.strip_prefix(EXECUTABLE_PREFIX)?.strip_suffix(env::consts::EXE_SUFFIX))
+            else {
+                continue;
+            };
+            if !name.is_empty() && entry.path().is_file() {
+                executables.entry(name.to_owned()).or_insert_with(|| entry.path());
+            }
+        }
+    }
+    executables
+}
+
+/// Blocking, since it runs while building the CLI.
+fn describe(name: &str, executable: PathBuf) -> anyhow::Result<Description> {
+    let mut child = std::process::Command::new(&executable)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("running {executable:?}"))?;
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    // Plugins that fail early may not read the request, their exit status tells
+    match stdin.write_all(json!({ "method": "describe" }).to_string().as_bytes()) {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e.into()),
+        _ => {}
+    }
+    drop(stdin);
+
+    let read_to_end = |mut pipe: Box<dyn Read + Send>| {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            pipe.read_to_end(&mut buf).map(|_| buf)
+        })
+    };
+    let stdout = read_to_end(Box::new(child.stdout.take().expect("piped stdout")));
+    let stderr = read_to_end(Box::new(child.stderr.take().expect("piped stderr")));
+    let deadline = Instant::now() + DESCRIBE_TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill()?;
+            child.wait()?;
+            anyhow::bail!("{executable:?} describe timed out after {DESCRIBE_TIMEOUT:?}");
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    let stdout = stdout.join().expect("stdout reader")?;
+    let stderr = stderr.join().expect("stderr reader")?;
+    if !status.success() {
+        anyhow::bail!("{executable:?} describe failed with status {status:?}: {}", String::from_utf8_lossy(&stderr));
+    }
+    let description: Description = serde_json::from_slice(&stdout).with_context(|| format!("decoding {executable:?} describe response"))?;
+    Ok(Description { name: name.to_owned(), executable, ..description })
+}
+
+impl Plugin {
+    pub fn from_arg_matches(description: Arc<Description>, matches: &ArgMatches) -> Self {
+        let args = description
+            .args
+            .iter()
+            .map(|arg| {
+                let value = if arg.flag {
+                    json!(matches.get_flag(&arg.name))
+                } else {
+                    json!(matches.get_one::<String>(&arg.name))
+                };
+                (arg.name.clone(), value)
+            })
+            .collect();
+        Self { description, args }
+    }
+}
+
+#[async_trait]
+impl VmProvider for Plugin {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
+        let step = steps::start();
+        log::info!("Spawning instance via {:?}...", self.description.executable);
+        let response = {
+            #[derive(Deserialize)]
+            struct Spawned {
+                ip: Ipv4Addr,
+                instance_id: Option<String>,
+                ssh_port: Option<u16>,
+                docker_port: Option<u16>,
+            }
+            call::<Spawned>(
+                self.description.clone(),
+                json!({ "method": "spawn", "args": self.args, "user_data": user_data }),
+            )
+            .await?
+        };
+        if let Some(instance_id) = &response.instance_id {
+            log::info!("{instance_id}");
+        }
+
+        let address = VmAddress {
+            ip: response.ip,
+            ssh_port: response.ssh_port.unwrap_or(22),
+            docker_port: response.docker_port.unwrap_or(2376),
+        };
+        let destroy = response.instance_id.map(|instance_id| {
+            let (description, args) = (self.description.clone(), self.args.clone());
+            async move {
+                call::<serde_json::Value>(description, json!({ "method": "terminate", "args": args, "instance_id": instance_id })).await?;
+                Ok(())
+            }
+            .boxed()
+        });
+
+        steps::end(step);
+        Ok(SpawnedVm { address, destroy, interruption_notice_command: None })
+    }
+
+    fn teardown_command(&self) -> &str {
+        self.description.teardown_command.as_deref().unwrap_or("shutdown -h now")
+    }
+}
+
+async fn call<T: DeserializeOwned>(description: Arc<Description>, request: serde_json::Value) -> anyhow::Result<T> {
+    let executable = &description.executable;
+    log::debug!("Calling {executable:?}: {}", request["method"]);
+    let mut child = process::Command::new(executable)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("running {executable:?}"))?;
+
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    match stdin.write_all(request.to_string().as_bytes()).await {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e.into()),
+        _ => {}
+    }
+    drop(stdin);
+
+    let stderr = child.stderr.take().expect("piped stderr");
+    let log_stderr = async {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            log::info!("{}: {line}", description.name);
+        }
+    };
+    let (output, ()) = tokio::join!(child.wait_with_output(), log_stderr);
+    let output = output?;
+    if !output.status.success() {
+        anyhow::bail!("{executable:?} {} failed with status {:?}", request["method"], output.status);
+    }
+    serde_json::from_slice(&output.stdout).with_context(|| format!("decoding {executable:?} {} response", request["method"]))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt as _;
+
+    /// Records each request next to itself and answers like a plugin would.
+    const FAKE_PLUGIN: &str = r#"#!/bin/sh
+request=$(cat)
+printf '%s\n' "$request" >> "$(dirname "$0")/requests"
+case "$request" in
+    *'"method":"describe"'*) echo '{"about": "Fake cloud", "args": [{"name": "region", "default": "eu-1"}, {"name": "big", "flag": true}]}' ;;
+    *'"method":"spawn"'*) echo 'launching' >&2; echo '{"ip": "203.0.113.7", "instance_id": "vm-123", "ssh_port": 2222}' ;;
+    *'"method":"terminate"'*) echo '{}' ;;
+    *) exit 1 ;;
+esac
+"#;
+
+    #[tokio::test]
+    async fn describe_spawn_terminate() {
+        let dir = env::temp_dir().join(format!("fleeting-plugin-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let executable = dir.join(format!("{EXECUTABLE_PREFIX}fake"));
+        fs::write(&executable, FAKE_PLUGIN).unwrap();
+        fs::set_permissions(&executable, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let description = Arc::new(describe("fake", executable).unwrap());
+        assert_eq!(description.name(), "fake");
+        assert_eq!(description.about, "Fake cloud");
+        let matches = description.command().try_get_matches_from(["fake", "--big"]).unwrap();
+        let plugin = Plugin::from_arg_matches(description, &matches);
+
+        let spawned = plugin.spawn("#!/bin/sh\necho \"hello\"\n").await.unwrap();
+        assert_eq!(spawned.address.ip, Ipv4Addr::new(203, 0, 113, 7));
+        assert_eq!(spawned.address.ssh_port, 2222);
+        assert_eq!(spawned.address.docker_port, 2376);
+        spawned.destroy.expect("destroy for instance_id").await.unwrap();
+
+        let requests = fs::read_to_string(dir.join("requests")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let requests = requests
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect::<Vec<serde_json::Value>>();
+        let args = json!({ "region": "eu-1", "big": true });
+        assert_eq!(
+            requests,
+            [
+                json!({ "method": "describe" }),
+                json!({ "method": "spawn", "args": args, "user_data": "#!/bin/sh\necho \"hello\"\n" }),
+                json!({ "method": "terminate", "args": args, "instance_id": "vm-123" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_failed_describe() {
+        let dir = env::temp_dir().join(format!("fleeting-plugin-test-failing-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let executable = dir.join(format!("{EXECUTABLE_PREFIX}failing"));
+        fs::write(&executable, "#!/bin/sh\necho 'no credentials' >&2\nexit 3\n").unwrap();
+        fs::set_permissions(&executable, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let error = describe("failing", executable).err().expect("describe fails");
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(format!("{error:#}").contains("no credentials"), "{error:#}");
+    }
+}
@@ -0,0 +1,408 @@
+use super::{instance_name, qemu::create_seed_iso, run_dirs::cache_dir, DestroyGuard, SpawnedVm, VmProvider};
+use crate::steps;
+use anyhow::Context as _;
+use async_trait::async_trait;
+use clap::Args;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::{multipart, Method};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    net::Ipv4Addr,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+use tokio::time::sleep;
+
+/// Proxmox VE
+#[derive(Args, Clone)]
+#[command(
+    override_usage = color_print::cstr! {r#"<bold>fleeting</bold> <bold>proxmox</bold> [OPTIONS] --url <<URL>> --template <<TEMPLATE>> [COMMAND]...
+
+Clones a cloud-init template VM, attaches a NoCloud seed ISO with the user_data
+and starts the clone. The clone and its ISO are destroyed when the run ends.
+
+<bold><underline>Authentication:</underline></bold>
+  - Environment variable PROXMOX_VE_API_TOKEN, as 'USER@REALM!TOKENID=SECRET'
+
+<bold><underline>Requirements:</underline></bold>
+  - Template VM from an Ubuntu 24.04 cloud image with qemu-guest-agent installed,
+    e.g. using 'virt-customize -a IMAGE --install qemu-guest-agent'
+  - Storage for ISO images on the template's node (see '--iso-storage')
+  - cloud-localds, genisoimage, mkisofs or xorrisofs
+
+<bold><underline>Limitations:</underline></bold>
+Clones are destroyed on SIGINT and SIGTERM too, but only power off when
+fleeting is killed with SIGKILL before the run ends. fleeting destroys stopped
+clones at the beginning of the run.
+
+"#},)]
+pub struct Proxmox {
+    /// API URL, e.g. 'https://pve.example.com:8006'.
+    #[arg(long)]
+    url: String,
+
+    /// Name or VMID of the template to clone.
+    #[arg(long)]
+    template: String,
+
+    /// Make a full clone instead of a linked clone.
+    #[arg(long)]
+    full_clone: bool,
+
+    /// Target storage of a full clone [default: same as template]
+    #[arg(long, requires = "full_clone")]
+    storage: Option<String>,
+
+    /// Storage to upload the seed ISO to.
+    #[arg(long, default_value = "local")]
+    iso_storage: String,
+
+    /// CPUs [default: same as template]
+    #[arg(long)]
+    cpus: Option<usize>,
+
+    /// Memory, in GBs [default: same as template]
+    #[arg(long)]
+    memory: Option<usize>,
+
+    /// PEM certificate to trust in addition to system roots, e.g. the cluster's /etc/pve/pve-root-ca.pem.
+    #[arg(long, value_name = "PATH")]
+    ca_cert: Option<PathBuf>,
+
+    /// Do not verify the server's TLS certificate.
+    #[arg(long)]
+    insecure: bool,
+}
+
+#[async_trait]
+impl VmProvider for Proxmox {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
+        let step = steps::start();
+        log::info!("Loading Proxmox VE configuration...");
+        let client = {
+            let token = env::var("PROXMOX_VE_API_TOKEN").context("PROXMOX_VE_API_TOKEN not set")?;
+            let mut http = reqwest::Client::builder().danger_accept_invalid_certs(self.insecure);
+            if let Some(ca_cert) = &self.ca_cert {
+                let pem = fs::read(ca_cert).with_context(|| format!("reading {ca_cert:?}"))?;
+                http = http.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+            }
+            let client = Client { http: http.build()?, url: self.url.trim_end_matches('/').to_owned(), token };
+
+            #[derive(Deserialize)]
+            struct Version {
+                version: String,
+            }
+            let version: Version = client.request(Method::GET, "/version", None).await?;
+            log::info!("Proxmox VE {}", version.version);
+            client
+        };
+
+        let step = step.next();
+        log::info!("Looking up template...");
+        let (template, resources) = {
+            let resources: Vec<Resource> = client.request(Method::GET, "/cluster/resources?type=vm", None).await?;
+            let template = resources
+                .iter()
+                .find(|r| r.r#type == "qemu" && r.template == 1 && (r.vmid.to_string() == self.template || r.name == self.template))
+                .ok_or(anyhow::format_err!("template VM '{}' not found", self.template))?
+                .clone();
+            (template, resources)
+        };
+        log::info!("{} on {}", template.vmid, template.node);
+        let node = template.node;
+
+        let step = step.next();
+        log::info!("Destroying stopped fleeting clones...");
+        {
+            let (stopped, others): (Vec<_>, Vec<_>) = resources
+                .into_iter()
+                .filter(|r| r.r#type == "qemu" && r.template == 0)
+                .partition(|r| r.name.starts_with("fleeting-") && r.status == "stopped");
+            for vm in &stopped {
+                destroy_vm(&client, &vm.node, vm.vmid).await?;
+            }
+            log::info!("{} destroyed", stopped.len());
+
+            log::debug!("Deleting orphaned seed ISOs...");
+            #[derive(Deserialize)]
+            struct Content {
+                volid: String,
+            }
+            let contents: Vec<Content> = client
+                .request(Method::GET, &format!("/nodes/{node}/storage/{}/content?content=iso", self.iso_storage), None)
+                .await?;
+            let existing = others.into_iter().map(|r| r.name).collect::<HashSet<_>>();
+            let prefix = format!("{}:iso/", self.iso_storage);
+            for content in contents {
+                let Some(name) = content.volid.strip_prefix(&prefix).and_then(|file| file.strip_suffix(".iso")) else {
+                    continue;
+                };
+                if name.starts_with("fleeting-") && !existing.contains(name) {
+                    if let Err(e) = delete_volume(&client, &node, &self.iso_storage, &content.volid).await {
+                        log::warn!("Failed to delete orphaned seed ISO {}: {e:#}", content.volid);
+                    }
+                }
+            }
+        }
+
+        let step = step.next();
+        log::info!("Cloning template...");
+        let name = instance_name();
+        let vmid = {
+            let vmid: String = client.request(Method::GET, "/cluster/nextid", None).await?;
+            let vmid = vmid.parse::<u32>()?;
+            let mut body = json!({ "newid": vmid, "name": name, "full": u8::from(self.full_clone) });
+            if let Some(storage) = &self.storage {
+                body["storage"] = json!(storage);
+            }
+            let upid: String = client
+                .request(Method::POST, &format!("/nodes/{node}/qemu/{}/clone", template.vmid), Some(body))
+                .await?;
+            client.wait_for_task(&node, &upid).await?;
+            vmid
+        };
+        log::info!("{vmid} ({name})");
+
+        // From here on, the clone must be destroyed when the run ends
+        let iso_volid = format!("{}:iso/{name}.iso", self.iso_storage);
+        let destroy = {
+            let (client, node, iso_storage, iso_volid) = (client.clone(), node.clone(), self.iso_storage.clone(), iso_volid.clone());
+            DestroyGuard::new(async move {
+                destroy_vm(&client, &node, vmid).await?;
+                // May not have been uploaded, orphans are deleted on the next run anyway
+                if let Err(e) = delete_volume(&client, &node, &iso_storage, &iso_volid).await {
+                    log::warn!("Failed to delete seed ISO: {e:#}");
+                }
+                Ok(())
+            })
+        };
+        let started = async {
+            let step = step.next();
+            log::info!("Uploading seed ISO...");
+            {
+                let run_dir = cache_dir("proxmox")?.join(&name);
+                fs::create_dir_all(&run_dir)?;
+                fs::write(run_dir.join("user-data"), user_data)?;
+                fs::write(run_dir.join("meta-data"), format!("instance-id: {name}\nlocal-hostname: {name}\n"))?;
+                create_seed_iso(&run_dir).await?;
+                let iso = fs::read(run_dir.join("seed.iso"))?;
+                fs::remove_dir_all(&run_dir)?;
+
+                let form = multipart::Form::new()
+                    .text("content", "iso")
+                    .part("filename", multipart::Part::bytes(iso).file_name(format!("{name}.iso")));
+                let upid = client.upload(&format!("/nodes/{node}/storage/{}/upload", self.iso_storage), form).await?;
+                client.wait_for_task(&node, &upid).await?;
+            }
+            log::info!("{iso_volid}");
+
+            let step = step.next();
+            log::info!("Configuring and starting clone...");
+            {
+                // The template's own cloud-init drive would compete with the seed ISO
+                let config: HashMap<String, serde_json::Value> = client.request(Method::GET, &format!("/nodes/{node}/qemu/{vmid}/config"), None).await?;
+                let cloudinit_drives = config
+                    .into_iter()
+                    .filter(|(key, value)| key != "ide2" && value.as_str().is_some_and(|v| v.contains("cloudinit")))
+                    .map(|(key, _)| key)
+                    .collect::<Vec<_>>();
+
+                let mut body = json!({ "ide2": format!("{iso_volid},media=cdrom"), "agent": "1" });
+                if !cloudinit_drives.is_empty() {
+                    body["delete"] = json!(cloudinit_drives.join(","));
+                }
+                if let Some(cpus) = self.cpus {
+                    body["cores"] = json!(cpus);
+                }
+                if let Some(memory) = self.memory {
+                    body["memory"] = json!(memory * 1024);
+                }
+                client
+                    .request::<serde_json::Value>(Method::POST, &format!("/nodes/{node}/qemu/{vmid}/config"), Some(body))
+                    .await?;
+
+                let upid: String = client.request(Method::POST, &format!("/nodes/{node}/qemu/{vmid}/status/start"), None).await?;
+                client.wait_for_task(&node, &upid).await?;
+            }
+
+            let step = step.next();
+            log::info!("Getting VM IP from guest agent...");
+            let ip = {
+                #[derive(Deserialize)]
+                struct Interfaces {
+                    result: Vec<Interface>,
+                }
+                #[derive(Deserialize)]
+                struct Interface {
+                    name: String,
+                    #[serde(rename = "ip-addresses", default)]
+                    ip_addresses: Vec<IpAddress>,
+                }
+                #[derive(Deserialize)]
+                struct IpAddress {
+                    #[serde(rename = "ip-address-type")]
+                    ip_address_type: String,
+                    #[serde(rename = "ip-address")]
+                    ip_address: String,
+                }
+
+                let deadline = SystemTime::now() + Duration::from_secs(180);
+                loop {
+                    if SystemTime::now() > deadline {
+                        anyhow::bail!("guest agent did not report an IPv4 address in time limit");
+                    }
+                    let interfaces: Interfaces = match client
+                        .request(Method::GET, &format!("/nodes/{node}/qemu/{vmid}/agent/network-get-interfaces"), None)
+                        .await
+                    {
+                        Ok(interfaces) => interfaces,
+                        Err(e) => {
+                            log::debug!("Guest agent not ready: {e:#}");
+                            sleep(Duration::from_secs(2)).await;
+                            continue;
+                        }
+                    };
+                    let ip = interfaces
+                        .result
+                        .into_iter()
+                        .filter(|interface| interface.name != "lo")
+                        .flat_map(|interface| interface.ip_addresses)
+                        .find(|address| address.ip_address_type == "ipv4");
+                    match ip {
+                        Some(address) => break address.ip_address.parse::<Ipv4Addr>()?,
+                        None => sleep(Duration::from_secs(2)).await,
+                    }
+                }
+            };
+
+            steps::end(step);
+            Ok(ip)
+        }
+        .await;
+
+        match started {
+            Ok(ip) => Ok(SpawnedVm { address: ip.into(), destroy: Some(destroy.disarm()), interruption_notice_command: None }),
+            Err(e) => {
+                log::info!("Destroying clone after failed start...");
+                if let Err(e) = destroy.disarm().await {
+                    log::error!("Failed to destroy clone: {e:#}");
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+struct Resource {
+    vmid: u32,
+    #[serde(default)]
+    name: String,
+    node: String,
+    r#type: String,
+    status: String,
+    #[serde(default)]
+    template: u8,
+}
+
+/// Stops the VM if needed, then deletes it along with its disks.
+async fn destroy_vm(client: &Client, node: &str, vmid: u32) -> anyhow::Result<()> {
+    #[derive(Deserialize)]
+    struct Status {
+        status: String,
+    }
+    let status: Status = client.request(Method::GET, &format!("/nodes/{node}/qemu/{vmid}/status/current"), None).await?;
+    if status.status != "stopped" {
+        let upid: String = client.request(Method::POST, &format!("/nodes/{node}/qemu/{vmid}/status/stop"), None).await?;
+        client.wait_for_task(node, &upid).await?;
+    }
+    let upid: String = client
+        .request(Method::DELETE, &format!("/nodes/{node}/qemu/{vmid}?purge=1&destroy-unreferenced-disks=1"), None)
+        .await?;
+    client.wait_for_task(node, &upid).await
+}
+
+async fn delete_volume(client: &Client, node: &str, storage: &str, volid: &str) -> anyhow::Result<()> {
+    // Responds with a task ID on recent versions, null on older ones
+    let upid: Option<String> = client
+        .request(
+            Method::DELETE,
+            // The volid's ':' and '/' must not split the path
+            &format!("/nodes/{node}/storage/{storage}/content/{}", utf8_percent_encode(volid, NON_ALPHANUMERIC)),
+            None,
+        )
+        .await?;
+    if let Some(upid) = upid {
+        client.wait_for_task(node, &upid).await?;
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+struct Client {
+    http: reqwest::Client,
+    url: String,
+    token: String,
+}
+
+impl Client {
+    async fn request<T: DeserializeOwned>(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> anyhow::Result<T> {
+        log::debug!("{method} {path}");
+        let mut request = self.http.request(method.clone(), self.api_url(path));
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        self.send(request, &method, path).await
+    }
+
+    /// Uploads a file as multipart form, returns the task ID.
+    async fn upload(&self, path: &str, form: multipart::Form) -> anyhow::Result<String> {
+        log::debug!("POST {path} (upload)");
+        let request = self.http.post(self.api_url(path)).multipart(form);
+        self.send(request, &Method::POST, path).await
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: reqwest::RequestBuilder, method: &Method, path: &str) -> anyhow::Result<T> {
+        let response = request
+            .header(reqwest::header::AUTHORIZATION, format!("PVEAPIToken={}", self.token))
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            // Parameter errors are detailed in the body, other reasons only in the status line
+            let reason = response.text().await.unwrap_or_default();
+            anyhow::bail!("{method} {path} failed: {status} {}", reason.trim());
+        }
+
+        #[derive(Deserialize)]
+        struct Data<T> {
+            data: T,
+        }
+        let data: Data<T> = response.json().await?;
+        Ok(data.data)
+    }
+
+    fn api_url(&self, path: &str) -> String {
+        format!("{}/api2/json{path}", self.url)
+    }
+
+    /// Waits for an asynchronous task (clone, start, ...) to finish successfully.
+    async fn wait_for_task(&self, node: &str, upid: &str) -> anyhow::Result<()> {
+        #[derive(Deserialize)]
+        struct TaskStatus {
+            status: String,
+            exitstatus: Option<String>,
+        }
+        loop {
+            let task: TaskStatus = self.request(Method::GET, &format!("/nodes/{node}/tasks/{upid}/status"), None).await?;
+            match (task.status.as_str(), task.exitstatus.as_deref()) {
+                ("running", _) => sleep(Duration::from_secs(1)).await,
+                (_, Some("OK")) => break Ok(()),
+                (_, exitstatus) => anyhow::bail!("task {upid} failed: {}", exitstatus.unwrap_or("unknown error")),
+            }
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use super::{
+    instance_name,
+    run_dirs::{cache_dir, RunDirs},
+    SpawnedVm, VmAddress, VmProvider,
+};
+use crate::{arch::Arch, command_ext::CommandExt as _, steps, ubuntu_release::UbuntuRelease};
+use anyhow::Context as _;
+use async_trait::async_trait;
+use clap::Args;
+use serde::Deserialize;
+use std::{
+    fs,
+    io::ErrorKind,
+    net::{Ipv4Addr, TcpListener},
+    path::{Path, PathBuf},
+    str::FromStr as _,
+    time::{Duration, SystemTime},
+};
+use tokio::{io::AsyncWriteExt as _, process::Command, time::sleep};
+
+/// Locations of UEFI firmware for arm64 guests on common distributions and Homebrew.
+const AARCH64_FIRMWARE_PATHS: &[&str] = &[
+    "/usr/share/qemu-efi-aarch64/QEMU_EFI.fd",
+    "/usr/share/AAVMF/AAVMF_CODE.fd",
+    "/usr/share/edk2/aarch64/QEMU_EFI.fd",
+    "/opt/homebrew/share/qemu/edk2-aarch64-code.fd",
+];
+
+/// QEMU with Ubuntu cloud images (local)
+#[derive(Args, Clone)]
+#[command(
+    override_usage = color_print::cstr! {r#"<bold>fleeting</bold> <bold>qemu</bold> [OPTIONS] [COMMAND]...
+
+Boots the Ubuntu cloud image for the host architecture, with user_data passed
+via a NoCloud seed ISO. Uses KVM (Linux) or HVF (macOS) when available.
+
+<bold><underline>Requirements:</underline></bold>
+  - qemu-system-x86_64 or qemu-system-aarch64, and qemu-img
+  - cloud-localds, genisoimage, mkisofs or xorrisofs
+  - For arm64 hosts, UEFI firmware (e.g. the qemu-efi-aarch64 package)
+
+The cloud image is cached in the user's cache directory (e.g. ~/.cache/fleeting/qemu).
+Delete it to download the latest release.
+
+"#},)]
+pub struct Qemu {
+    /// CPUs.
+    #[arg(long, default_value_t = 2)]
+    cpus: usize,
+
+    /// Memory, in GBs.
+    #[arg(long, default_value_t = 2)]
+    memory: usize,
+
+    /// Disk size, in GiBs.
+    #[arg(long, default_value_t = 10)]
+    disk: usize,
+
+    /// Attach to an existing tap device instead of using user-mode networking.
+    ///
+    /// The tap device must be bridged to a network with a DHCP server. The VM's IP is looked up in the host's ARP table.
+    #[arg(long, value_name = "IFNAME")]
+    tap: Option<String>,
+
+    /// Release of Canonical's Ubuntu image.
+    #[arg(long, default_value = "24.04")]
+    ubuntu_release: UbuntuRelease,
+}
+
+#[async_trait]
+impl VmProvider for Qemu {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
+        let step = steps::start();
+        log::info!("Checking qemu installation...");
+        let arch = Arch::from_str(std::env::consts::ARCH)?;
+        let qemu_system = format!("qemu-system-{}", arch.as_uname_m());
+        {
+            let version = Command::new(&qemu_system).arg("--version").capture_stdout().await?;
+            log::debug!("{}", String::from_utf8_lossy(&version).trim());
+            let version = Command::new("qemu-img").arg("--version").capture_stdout().await?;
+            log::debug!("{}", String::from_utf8_lossy(&version).trim());
+        }
+        let cache_dir = cache_dir("qemu")?;
+        let run_dirs = RunDirs::new("qemu", "qemu")?;
+
+        let step = step.next();
+        log::info!("Purging old stopped fleeting VMs...");
+        {
+            let purged = run_dirs.purge_stopped()?;
+            log::info!("{purged} purged");
+        }
+
+        let step = step.next();
+        log::info!("Downloading Ubuntu cloud image if needed...");
+        let base_image = {
+            let file_name = format!("ubuntu-{}-server-cloudimg-{}.img", self.ubuntu_release, arch.as_dpkg());
+            let path = cache_dir.join(&file_name);
+            if path.exists() {
+                log::info!("{path:?} (cached)");
+            } else {
+                let url = format!("https://cloud-images.ubuntu.com/releases/{}/release/{file_name}", self.ubuntu_release);
+                download(&url, &path).await.with_context(|| format!("downloading {url}"))?;
+                log::info!("{path:?} (downloaded)");
+            }
+            path
+        };
+
+        let step = step.next();
+        log::info!("Launching a VM...");
+        let name = instance_name();
+        let mac = format!(
+            "52:54:00:{:02x}:{:02x}:{:02x}",
+            rand::random::<u8>(),
+            rand::random::<u8>(),
+            rand::random::<u8>()
+        );
+        let user_mode_ports = {
+            let run_dir = run_dirs.create(&name)?;
+
+            log::debug!("Creating overlay disk...");
+            Command::new("qemu-img")
+                .args(["create", "-f", "qcow2", "-F", "qcow2", "-b"])
+                .arg(&base_image)
+                .arg(run_dir.join("disk.qcow2"))
+                .arg(format!("{}G", self.disk))
+                .capture_stdout()
+                .await?;
+
+            log::debug!("Creating seed ISO...");
+            fs::write(run_dir.join("user-data"), user_data)?;
+            fs::write(run_dir.join("meta-data"), format!("instance-id: {name}\nlocal-hostname: {name}\n"))?;
+            create_seed_iso(&run_dir).await?;
+
+            let mut command = Command::new(&qemu_system);
+            command
+                .args(["-name", &name])
+                .args(["-smp", &self.cpus.to_string()])
+                .args(["-m", &format!("{}G", self.memory)])
+                .args(["-display", "none", "-daemonize"])
+                .arg("-pidfile")
+                .arg(run_dir.join("pid"))
+                .arg("-serial")
+                .arg(format!("file:{}", run_dir.join("console.log").display()))
+                .arg("-drive")
+                .arg(format!("file={},if=virtio,format=qcow2", run_dir.join("disk.qcow2").display()))
+                .arg("-drive")
+                .arg(format!("file={},if=virtio,format=raw,readonly=on", run_dir.join("seed.iso").display()));
+
+            if cfg!(target_os = "linux") && Path::new("/dev/kvm").exists() {
+                command.args(["-accel", "kvm", "-cpu", "host"]);
+            } else if cfg!(target_os = "macos") {
+                command.args(["-accel", "hvf", "-cpu", "host"]);
+            } else {
+                log::warn!("No hardware acceleration available, the VM will be slow");
+                command.args(["-accel", "tcg", "-cpu", "max"]);
+            }
+
+            match arch {
+                Arch::Amd64 => {
+                    command.args(["-machine", "q35"]);
+                }
+                Arch::Arm64 => {
+                    let firmware = AARCH64_FIRMWARE_PATHS
+                        .iter()
+                        .find(|path| Path::new(path).exists())
+                        .ok_or(anyhow::format_err!("UEFI firmware not found in: {AARCH64_FIRMWARE_PATHS:?}"))?;
+                    command.args(["-machine", "virt", "-bios", firmware]);
+                }
+            }
+
+            let user_mode_ports = if let Some(tap) = &self.tap {
+                command.args(["-netdev", &format!("tap,id=net0,ifname={tap},script=no,downscript=no")]);
+                None
+            } else {
+                let ssh_port = free_local_port()?;
+                let docker_port = free_local_port()?;
+                command.args([
+                    "-netdev",
+                    &format!("user,id=net0,hostfwd=tcp:127.0.0.1:{ssh_port}-:22,hostfwd=tcp:127.0.0.1:{docker_port}-:2376"),
+                ]);
+                Some((ssh_port, docker_port))
+            };
+            command.args(["-device", &format!("virtio-net-pci,netdev=net0,mac={mac}")]);
+
+            command.capture_stdout().await.context("starting qemu")?;
+            user_mode_ports
+        };
+        log::info!("{name}");
+
+        let step = step.next();
+        log::info!("Getting VM IP...");
+        let address = match user_mode_ports {
+            Some((ssh_port, docker_port)) => VmAddress { ip: Ipv4Addr::LOCALHOST, ssh_port, docker_port },
+            None => {
+                #[derive(Deserialize)]
+                struct Neighbor {
+                    dst: String,
+                    lladdr: Option<String>,
+                }
+                let deadline = SystemTime::now() + Duration::from_secs(120);
+                loop {
+                    if SystemTime::now() > deadline {
+                        anyhow::bail!("VM with MAC {mac} did not appear in the ARP table in time limit");
+                    }
+                    let neighbors: Vec<Neighbor> = Command::new("ip").args(["-json", "-4", "neigh", "show"]).capture_json().await?;
+                    if let Some(neighbor) = neighbors.into_iter().find(|n| n.lladdr.as_deref() == Some(mac.as_str())) {
+                        break neighbor.dst.parse::<Ipv4Addr>()?.into();
+                    }
+                    sleep(Duration::from_secs(1)).await;
+                }
+            }
+        };
+
+        steps::end(step);
+        Ok(address.into())
+    }
+}
+
+pub(super) async fn download(url: &str, path: &Path) -> anyhow::Result<()> {
+    let partial_path = PathBuf::from(format!("{}.partial", path.display()));
+    let mut response = reqwest::get(url).await?.error_for_status()?;
+    let mut file = tokio::fs::File::create(&partial_path).await?;
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    fs::rename(&partial_path, path)?;
+    Ok(())
+}
+
+/// Creates `seed.iso` labeled `cidata` from `user-data` and `meta-data` using whichever tool is installed.
+pub(super) async fn create_seed_iso(run_dir: &Path) -> anyhow::Result<()> {
+    let mkisofs_args = ["-output", "seed.iso", "-volid", "cidata", "-joliet", "-rock", "user-data", "meta-data"];
+    let candidates: [(&str, &[&str]); 4] = [
+        ("cloud-localds", &["seed.iso", "user-data", "meta-data"]),
+        ("genisoimage", &mkisofs_args),
+        ("mkisofs", &mkisofs_args),
+        ("xorrisofs", &mkisofs_args),
+    ];
+    for (program, args) in candidates {
+        match Command::new(program).args(args).current_dir(run_dir).output().await {
+            Ok(output) if output.status.success() => return Ok(()),
+            Ok(output) => anyhow::bail!("{program} failed with status {:?}: {}", output.status, String::from_utf8_lossy(&output.stderr)),
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    anyhow::bail!("none of {:?} found", candidates.map(|(program, _)| program))
+}
+
+fn free_local_port() -> anyhow::Result<u16> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    Ok(listener.local_addr()?.port())
+}
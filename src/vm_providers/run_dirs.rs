@@ -0,0 +1,76 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Working directories of local VMs (disks, seeds, logs), one per run.
+///
+/// Each contains a `pid` file of the process running the VM, which is used to detect stopped VMs.
+pub struct RunDirs {
+    path: PathBuf,
+    process_name_prefix: &'static str,
+}
+
+impl RunDirs {
+    /// `~/.cache/fleeting/<provider>/runs`. Only processes whose name starts with `process_name_prefix` are considered running VMs.
+    pub fn new(provider: &str, process_name_prefix: &'static str) -> anyhow::Result<Self> {
+        let path = cache_dir(provider)?.join("runs");
+        fs::create_dir_all(&path)?;
+        Ok(Self { path, process_name_prefix })
+    }
+
+    pub fn create(&self, name: &str) -> anyhow::Result<PathBuf> {
+        let path = self.path.join(name);
+        fs::create_dir(&path)?;
+        Ok(path)
+    }
+
+    /// Removes directories of VMs that are no longer running. Returns the number removed.
+    pub fn purge_stopped(&self) -> anyhow::Result<usize> {
+        let mut purged = 0;
+        for entry in fs::read_dir(&self.path)? {
+            let run_dir = entry?.path();
+            if !self.is_running(&run_dir)? {
+                log::debug!("Removing {run_dir:?}");
+                fs::remove_dir_all(&run_dir)?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Directories of VMs that are running or launching, after `purge_stopped`.
+    pub fn list(&self) -> anyhow::Result<Vec<PathBuf>> {
+        Ok(fs::read_dir(&self.path)?.map(|entry| Ok(entry?.path())).collect::<std::io::Result<_>>()?)
+    }
+
+    /// Directories without a pid file are considered running for a while, since they may be in the middle of launching.
+    fn is_running(&self, run_dir: &Path) -> anyhow::Result<bool> {
+        let pid = match fs::read_to_string(run_dir.join("pid")) {
+            Ok(pid) => pid.trim().parse::<u32>()?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let age = fs::metadata(run_dir)?.modified()?.elapsed().unwrap_or_default();
+                return Ok(age < Duration::from_secs(3600));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let pid = sysinfo::Pid::from_u32(pid);
+        let mut system = sysinfo::System::new();
+        system.refresh_processes_specifics(sysinfo::ProcessesToUpdate::Some(&[pid]), sysinfo::ProcessRefreshKind::new());
+        Ok(system
+            .process(pid)
+            .is_some_and(|process| process.name().to_string_lossy().starts_with(self.process_name_prefix)))
+    }
+}
+
+/// `~/.cache/fleeting/<provider>`, for downloaded images and such.
+pub fn cache_dir(provider: &str) -> anyhow::Result<PathBuf> {
+    let path = dirs::cache_dir()
+        .ok_or(anyhow::format_err!("cannot locate cache dir"))?
+        .join("fleeting")
+        .join(provider);
+    fs::create_dir_all(&path)?;
+    Ok(path)
+}
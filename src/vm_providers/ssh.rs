@@ -0,0 +1,131 @@
+use super::{SpawnedVm, VmProvider};
+use crate::{
+    ssh::{ChannelExt as _, StreamMode},
+    steps,
+};
+use anyhow::Context as _;
+use async_trait::async_trait;
+use base64::prelude::*;
+use clap::Args;
+use indoc::indoc;
+use std::{path::PathBuf, sync::Arc};
+
+/// Only stops the dockerd the worker started, and only removes the keys `user_data` marked.
+const TEARDOWN_COMMAND: &str = indoc! {r#"
+    if [ -e /fleeting/dockerd.pid ]
+    then
+        pid=$(cat /fleeting/dockerd.pid)
+        kill "$pid" || true
+        # dockerd stops its containers first, give up on those that hang
+        for _ in $(seq 60)
+        do
+            kill -0 "$pid" 2>/dev/null || break
+            sleep 1
+        done
+        kill -KILL "$pid" 2>/dev/null || true
+    fi
+    rm -rf /fleeting /root/fleeting-init /tmp/ca.pem /tmp/server-cert.pem /tmp/server-key.pem
+    sed -i '/ fleeting-run$/d' /root/.ssh/authorized_keys
+"#};
+
+/// Existing Linux host (bring your own)
+#[derive(Args, Clone)]
+#[command(
+    override_usage = color_print::cstr! {r#"<bold>fleeting</bold> <bold>ssh</bold> [OPTIONS] --host <<HOST>> --identity <<PATH>> [COMMAND]...
+
+Instead of launching a VM, fleeting sets up dockerd on an existing host and
+tears it down (stops dockerd, removes keys and certificates) once done.
+
+<bold><underline>Requirements:</underline></bold>
+  - Linux host with bash, curl and iptables
+  - The host key must be present in ~/.ssh/known_hosts
+  - '--user' must be root or have passwordless sudo
+  - sshd on port 22 must permit public key logins for root
+  - No other dockerd may be running on the host (fleeting only stops its own)
+
+dockerd is installed into /usr/local/bin and its data in /var/lib/docker is
+kept between runs. Only one fleeting run can use a host at a time.
+
+"#},)]
+pub struct Ssh {
+    /// Hostname or IPv4 address.
+    #[arg(long)]
+    host: String,
+
+    #[arg(long, default_value = "root")]
+    user: String,
+
+    /// Private key for authenticating '--user'.
+    #[arg(long, value_name = "PATH")]
+    identity: PathBuf,
+}
+
+#[async_trait]
+impl VmProvider for Ssh {
+    async fn spawn(&self, user_data: &str) -> anyhow::Result<SpawnedVm> {
+        let step = steps::start();
+        log::info!("Connecting to host...");
+        let (ip, session) = {
+            let ip = tokio::net::lookup_host((self.host.as_str(), 22))
+                .await?
+                .find_map(|addr| match addr.ip() {
+                    std::net::IpAddr::V4(ip) => Some(ip),
+                    std::net::IpAddr::V6(_) => None,
+                })
+                .ok_or(anyhow::format_err!("{} has no IPv4 address", self.host))?;
+            log::debug!("{ip}");
+
+            let key_pair = russh::keys::load_secret_key(&self.identity, None).with_context(|| format!("loading {:?}", self.identity))?;
+            let config = Arc::new(russh::client::Config::default());
+            let handler = KnownHostsHandler { host: self.host.clone() };
+            let mut session = russh::client::connect(config, (ip, 22), handler).await?;
+            if !session.authenticate_publickey(&self.user, Arc::new(key_pair)).await? {
+                anyhow::bail!("authentication as {} failed", self.user);
+            }
+            (ip, session)
+        };
+
+        let step = step.next();
+        log::info!("Starting setup...");
+        {
+            let command = if self.user == "root" { "bash -s" } else { "sudo -n bash -s" };
+            let launch_script = include_str!("ssh_launch.sh").replace("{{user_data_base64}}", &BASE64_STANDARD.encode(user_data));
+            session
+                .channel_open_session()
+                .await?
+                .exec_to_completion(
+                    command,
+                    true,
+                    Some(launch_script.as_bytes()),
+                    StreamMode::Log { level: log::Level::Debug, prefix: "launch" },
+                    StreamMode::Log { level: log::Level::Warn, prefix: "launch" },
+                )
+                .await
+                .context("starting setup")?;
+            session.disconnect(russh::Disconnect::ByApplication, "", "").await?;
+        }
+
+        steps::end(step);
+        Ok(ip.into())
+    }
+
+    fn teardown_command(&self) -> &str {
+        TEARDOWN_COMMAND
+    }
+}
+
+struct KnownHostsHandler {
+    host: String,
+}
+
+#[async_trait]
+impl russh::client::Handler for KnownHostsHandler {
+    type Error = russh::Error;
+    async fn check_server_key(&mut self, server_public_key: &russh::keys::key::PublicKey) -> Result<bool, Self::Error> {
+        let known = russh::keys::check_known_hosts(&self.host, 22, server_public_key)?;
+        if !known {
+            log::error!("Host key for {} not found in known_hosts", self.host);
+        }
+        Ok(known)
+    }
+}
@@ -0,0 +1,50 @@
+#!/bin/bash
+set -eu -o pipefail
+
+# Whether /fleeting is held by a run that is still setting up or running dockerd, as opposed to left behind by a
+# reboot or a killed run
+in_use() {
+    # The lock's files are written right after taking it
+    if [ ! -e /fleeting/init.pid ] && [ $(( $(date +%s) - $(stat --format %Y /fleeting) )) -lt 60 ]
+    then
+        return 0
+    fi
+    # pids don't survive a reboot, but may have been reused since
+    if [ -e /fleeting/boot_id ] && [ "$(cat /fleeting/boot_id)" != "$(cat /proc/sys/kernel/random/boot_id)" ]
+    then
+        return 1
+    fi
+    for pid_file in /fleeting/init.pid /fleeting/dockerd.pid
+    do
+        if [ -e "$pid_file" ] && kill -0 "$(cat "$pid_file")" 2>/dev/null
+        then
+            return 0
+        fi
+    done
+    return 1
+}
+
+# mkdir is atomic, so only one run can take the lock
+if ! mkdir /fleeting 2>/dev/null
+then
+    if in_use
+    then
+        echo "/fleeting exists, host is in use by another fleeting run" >&2
+        exit 1
+    fi
+    echo "Removing stale /fleeting" >&2
+    rm -rf /fleeting
+    if ! mkdir /fleeting 2>/dev/null
+    then
+        echo "/fleeting was taken by another fleeting run" >&2
+        exit 1
+    fi
+fi
+cat /proc/sys/kernel/random/boot_id >/fleeting/boot_id
+
+base64 -d >/root/fleeting-init <<'USER_DATA'
+{{user_data_base64}}
+USER_DATA
+chmod +x /root/fleeting-init
+setsid nohup /root/fleeting-init >/var/log/fleeting-init.log 2>&1 </dev/null &
+echo $! >/fleeting/init.pid
@@ -1,23 +1,24 @@
 use crate::{
     arch::Arch,
-    docker_context::DockerContext,
+    docker_context::{DockerContext, InstanceInterrupted},
     docker_releases::get_docker_releases,
     docker_tls::DockerCA,
+    shutdown,
     ssh::{ChannelExt as _, StreamMode},
     steps,
-    vm_providers::{SomeVmProvider, VmProvider},
+    vm_providers::{SomeVmProvider, SpawnedVm, VmProvider},
 };
 use async_trait::async_trait;
 use clap::Args;
 use core::str;
-use futures::FutureExt as _;
+use futures::{future::BoxFuture, FutureExt as _, TryFutureExt as _};
 use rand::distributions::Alphanumeric;
 use rand::Rng;
 use russh::keys::PublicKeyBase64;
 use semver::VersionReq;
 use std::{
     fs,
-    net::Ipv4Addr,
+    net::SocketAddrV4,
     sync::Arc,
     time::{Duration, SystemTime},
 };
@@ -53,9 +54,52 @@ impl WorkerConfig {
     /// The process that "owns" the remote VM (= sends heartbeats).
     /// `task` receives a docker context name.
     pub async fn spawn(&self) -> anyhow::Result<DockerContext> {
+        let candidates = self.vm_provider.hedged();
+        let mut destroys = PendingDestroys(candidates.iter().map(|_| None).collect());
+        let result = if let [provider] = &*candidates {
+            self.spawn_and_set_up(provider, &mut destroys.0[0])
+                .await
+                .map(|docker_context| (0, docker_context))
+        } else {
+            // Dropping the others once one wins stops their keepalives, so their VMs shut down.
+            log::info!("Hedging across {} providers...", candidates.len());
+            let attempts = candidates.iter().zip(&mut destroys.0).enumerate().map(|(i, (provider, destroy))| {
+                let attempt = self.spawn_and_set_up(provider, destroy).map_ok(move |docker_context| (i, docker_context));
+                steps::scope(provider.label(), attempt).boxed()
+            });
+            futures::future::select_ok(attempts).await.map(|(winner, _losers)| winner)
+        };
+
+        let winner = result.as_ref().ok().map(|(i, _)| *i);
+        let unused = destroys
+            .0
+            .iter_mut()
+            .enumerate()
+            .filter(|(i, _)| Some(*i) != winner)
+            .filter_map(|(_, destroy)| destroy.take())
+            .collect::<Vec<_>>();
+        if !unused.is_empty() {
+            log::info!("Destroying unused instances...");
+            for result in futures::future::join_all(unused).await {
+                if let Err(e) = result {
+                    log::error!("Failed to destroy instance: {e:#}");
+                }
+            }
+        }
+
+        let (winner, docker_context) = result?;
+        Ok(docker_context.with_destroy(destroys.0[winner].take()))
+    }
+
+    /// Stores the provider's destroy hook in `destroy` as soon as the VM exists, so that it runs even if setup fails.
+    async fn spawn_and_set_up(
+        &self,
+        vm_provider: &SomeVmProvider,
+        destroy: &mut Option<BoxFuture<'static, anyhow::Result<()>>>,
+    ) -> anyhow::Result<DockerContext> {
         let step = steps::start();
         log::info!("Starting an ephemeral instance...");
-        let (ip, key_pair, otp) = {
+        let (address, key_pair, otp, interruption_notice_command) = {
             log::debug!("Generating ephemeral ssh key...");
             let key_pair = russh::keys::key::KeyPair::generate_ed25519().expect("key generated");
             let authorized_key = format!("{} {} fleeting-ephemeral", key_pair.name(), key_pair.public_key_base64());
@@ -87,19 +131,20 @@ impl WorkerConfig {
             let user_data = include_str!("user_data_template.sh")
                 .replace("{{authorized_keys}}", &authorized_keys.join("\n"))
                 .replace("{{keepalive_timeout}}", &KEEPALIVE_TIMEOUT.as_secs().to_string())
-                .replace("{{otp}}", &otp);
-            let ip = self.vm_provider.spawn(&user_data).await?;
-            (ip, key_pair, otp)
+                .replace("{{otp}}", &otp); // `SomeVmProvider` fills in {{teardown_command}}
+            let SpawnedVm { address, destroy: vm_destroy, interruption_notice_command } = vm_provider.spawn(&user_data).await?;
+            *destroy = vm_destroy;
+            (address, key_pair, otp, interruption_notice_command)
         };
-        log::info!("{ip}");
+        log::info!("{}", address.ip);
 
-        let step: _ = step.next();
+        let step = step.next();
         log::info!("Attempting to connect to instance...");
-        let ssh_tcp_stream = wait_for_tcp_stream(ip, 22).await?;
+        let ssh_tcp_stream = wait_for_tcp_stream(address.ssh(), Some(b"SSH-")).await?;
 
-        let step: _ = step.next();
+        let step = step.next();
         log::info!("Waiting for instance setup to complete..."); // == ssh can authenticate
-        let (session, mut keepalive_handle) = {
+        let (session, mut keepalive_handle, interruption_handle) = {
             let config = Arc::new(russh::client::Config {
                 // inactivity_timeout: Some(Duration::from_secs(60)), // needed?
                 ..<_>::default()
@@ -149,10 +194,43 @@ impl WorkerConfig {
             .remote_handle();
             tokio::spawn(keepalive);
 
-            (session, keepalive_handle)
+            let interruption_handle = match interruption_notice_command {
+                Some(command) => {
+                    log::debug!("Starting interruption watcher...");
+                    let mut interruption_channel = session.channel_open_session().await?;
+                    let (watcher, interruption_handle) = async move {
+                        let outcome = interruption_channel
+                            .exec_to_completion(
+                                command,
+                                true,
+                                None,
+                                StreamMode::Capture,
+                                StreamMode::Log { level: log::Level::Warn, prefix: "interruption watcher" },
+                            )
+                            .await;
+                        match outcome {
+                            Ok(outcome) => {
+                                let notice = String::from_utf8_lossy(&outcome.stdout.unwrap_or_default()).trim().to_owned();
+                                Err(InstanceInterrupted { notice }.into())
+                            }
+                            Err(e) => {
+                                // Not worth failing over, keepalive notices if the instance is gone
+                                log::warn!("Interruption watcher failed: {e:#}");
+                                futures::future::pending().await
+                            }
+                        }
+                    }
+                    .remote_handle();
+                    tokio::spawn(watcher);
+                    Some(interruption_handle)
+                }
+                None => None,
+            };
+
+            (session, keepalive_handle, interruption_handle)
         };
 
-        let step: _ = step.next();
+        let step = step.next();
         log::info!("Installing dockerd...");
         {
             log::debug!("Determining VM architecture...");
@@ -190,10 +268,10 @@ impl WorkerConfig {
                 .await?;
         }
 
-        let step: _ = step.next();
+        let step = step.next();
         log::info!("Setting up docker keys...");
         let ca = DockerCA::new()?;
-        let server_tls = ca.create_server_cert(ip)?;
+        let server_tls = ca.create_server_cert(address.ip)?;
         let client_tls = ca.create_client_cert()?;
         session
             .channel_open_session()
@@ -211,20 +289,20 @@ impl WorkerConfig {
             .write_file("/tmp/server-key.pem", server_tls.key_pair.serialize_pem().as_bytes())
             .await?;
 
-        let step: _ = step.next();
+        let step = step.next();
         log::info!("Waiting for dockerd to start...");
         let docker_context = {
             log::debug!("Starting dockerd...");
             let mut dockerd_session = session.channel_open_session().await?;
             let (dockerd, dockerd_handle) = async move {
-                let command = "dockerd -H tcp://0.0.0.0:2376 --tlsverify --tlscacert=/tmp/ca.pem --tlscert=/tmp/server-cert.pem --tlskey=/tmp/server-key.pem";
+                let command = "dockerd --pidfile=/fleeting/dockerd.pid -H tcp://0.0.0.0:2376 --tlsverify --tlscacert=/tmp/ca.pem --tlscert=/tmp/server-cert.pem --tlskey=/tmp/server-key.pem";
                 dockerd_session.exec_passthru("dockerd", command).await
             }
             .remote_handle();
             tokio::spawn(dockerd);
 
             log::debug!("Waiting for port to become reachable...");
-            let tcp_stream_future = wait_for_tcp_stream(ip, 2376);
+            let tcp_stream_future = wait_for_tcp_stream(address.docker(), None);
             tokio::select! {
                 result = tcp_stream_future => {
                     result?;
@@ -238,7 +316,15 @@ impl WorkerConfig {
                 .custom_context_name
                 .to_owned()
                 .unwrap_or_else(|| format!("fleeting-{}", std::process::id()));
-            DockerContext::new(context_name, ip, &ca.cert, &client_tls, keepalive_handle, dockerd_handle)?
+            DockerContext::new(
+                context_name,
+                address.docker(),
+                &ca.cert,
+                &client_tls,
+                keepalive_handle,
+                dockerd_handle,
+                interruption_handle,
+            )?
         };
         log::info!("Docker context '{}' ready.", docker_context.name());
 
@@ -257,23 +343,59 @@ impl russh::client::Handler for ClientHandler {
     }
 }
 
+/// Destroy hooks of the VMs being set up, left to `shutdown::destroy_pending` if setup is dropped midway,
+/// e.g. on a signal or once the '--while' PID exits first.
+struct PendingDestroys(Vec<Option<BoxFuture<'static, anyhow::Result<()>>>>);
+
+impl Drop for PendingDestroys {
+    fn drop(&mut self) {
+        for destroy in self.0.drain(..).flatten() {
+            shutdown::defer_destroy(destroy);
+        }
+    }
+}
+
 /// Tries to connect for 60 seconds
-async fn wait_for_tcp_stream(ip: Ipv4Addr, port: u16) -> anyhow::Result<TcpStream> {
+async fn wait_for_tcp_stream(addr: SocketAddrV4, banner: Option<&[u8]>) -> anyhow::Result<TcpStream> {
     let deadline = SystemTime::now() + Duration::from_secs(60);
     loop {
         if SystemTime::now() > deadline {
             anyhow::bail!("Could not open tcp stream in the deadline");
         }
-        match timeout(Duration::from_secs(3), TcpStream::connect((ip, port))).await {
-            Ok(Ok(stream)) => break Ok(stream),
-            Ok(Err(e)) => {
-                log::debug!("TCP connect failed: {e:#}");
-                sleep(Duration::from_secs(1)).await;
-            }
-            Err(_) => {
-                log::debug!("TCP connect timeout out");
+        match connect_serving(addr, banner).await {
+            Ok(stream) => break Ok(stream),
+            Err(e) => {
+                log::debug!("{e:#}");
                 sleep(Duration::from_secs(1)).await;
             }
         }
     }
 }
+
+/// Connects once, succeeding only if the service behind `addr` is actually serving.
+///
+/// Port forwarders (slirp `hostfwd`, docker-proxy, `kubectl port-forward`) accept connections before anything listens
+/// behind them and then close the stream. So the service must send `banner` if one is given, or else not hang up
+/// within a moment: TLS servers like dockerd wait for the client to speak first.
+pub(crate) async fn connect_serving(addr: SocketAddrV4, banner: Option<&[u8]>) -> anyhow::Result<TcpStream> {
+    let stream = match timeout(Duration::from_secs(3), TcpStream::connect(addr)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => anyhow::bail!("TCP connect failed: {e:#}"),
+        Err(_) => anyhow::bail!("TCP connect timed out"),
+    };
+    let serving = match banner {
+        Some(banner) => {
+            let mut buf = vec![0; banner.len()];
+            match timeout(Duration::from_secs(3), stream.peek(&mut buf)).await {
+                Ok(Ok(n)) => n > 0 && banner.starts_with(&buf[..n]),
+                Ok(Err(_)) | Err(_) => false,
+            }
+        }
+        None => match timeout(Duration::from_millis(300), stream.peek(&mut [0])).await {
+            Ok(Ok(0) | Err(_)) => false,
+            Ok(Ok(_)) | Err(_) => true,
+        },
+    };
+    anyhow::ensure!(serving, "TCP stream opened, but nothing is serving behind it yet");
+    Ok(stream)
+}
@@ -0,0 +1,36 @@
+//! End-to-end test of `WorkerConfig::spawn` against the local container provider:
+//! SSH auth, OTP readback, dockerd install, TLS and the docker context.
+//!
+//! Needs Docker, run with `cargo test -- --ignored`.
+
+use clap::Parser;
+use fleeting::worker::WorkerConfig;
+use tokio::process::Command;
+
+#[derive(Parser)]
+struct TestCli {
+    #[command(flatten)]
+    worker: WorkerConfig,
+}
+
+#[tokio::test]
+#[ignore = "needs Docker"]
+async fn docker_context_works() {
+    let context_name = format!("fleeting-test-{}", std::process::id());
+    let cli = TestCli::parse_from(["fleeting", "container", "--context-name", &context_name]);
+    let docker_context = cli.worker.spawn().await.expect("docker context created");
+    assert_eq!(docker_context.name(), context_name);
+
+    let server_version = docker_context
+        .wrap(async {
+            let output = Command::new("docker")
+                .args(["--context", &context_name, "version", "--format", "{{.Server.Version}}"])
+                .output()
+                .await?;
+            anyhow::ensure!(output.status.success(), "docker version failed: {}", String::from_utf8_lossy(&output.stderr));
+            Ok(String::from_utf8(output.stdout)?)
+        })
+        .await
+        .expect("docker version");
+    assert!(!server_version.trim().is_empty());
+}